@@ -1,87 +1,325 @@
 use chrono::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::sync::Arc;
+use std::time::SystemTime;
 
-use crate::actuators::cron_processor::{Action, CronProcessor};
-use crate::coap::{basic, CborMap};
-use crate::state::{HcState, HvacState};
+use crate::actuators::cron_processor::{Action, CronProcessor, Description};
+use crate::actuators::Trigger;
+use crate::coap::{basic, AcCommand};
+use crate::state::{HcState, HvacState, Occupancy};
+use crate::web;
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AcMode {
+    Auto,
+    Cool,
+    Dry,
+    Fan,
+    Heat,
+}
+
+impl AcMode {
+    fn as_char(&self) -> char {
+        match self {
+            AcMode::Auto => 'a',
+            AcMode::Cool => 'c',
+            AcMode::Dry => 'd',
+            AcMode::Fan => 'f',
+            AcMode::Heat => 'h',
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FanSpeed {
+    Auto,
+    Quiet,
+    Low,
+    Medium,
+    High,
+}
+
+impl FanSpeed {
+    fn as_char(&self) -> char {
+        match self {
+            FanSpeed::Auto => 'a',
+            FanSpeed::Quiet => 'q',
+            FanSpeed::Low => 'l',
+            FanSpeed::Medium => 'm',
+            FanSpeed::High => 'h',
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+pub struct AcSetting {
+    pub on: bool,
+    pub mode: AcMode,
+    #[serde(default = "AcSetting::default_fan")]
+    pub fan: FanSpeed,
+    pub temperature: u8,
+}
+
+impl AcSetting {
+    fn default_fan() -> FanSpeed {
+        FanSpeed::Auto
+    }
+
+    const fn new(on: bool, mode: AcMode, temperature: u8) -> Self {
+        Self {
+            on,
+            mode,
+            fan: FanSpeed::Auto,
+            temperature,
+        }
+    }
+}
+
+#[derive(Clone, Deserialize)]
+pub struct AcStep {
+    /// E.g. "07:00", "09:00 on weekends" or "sunset - 30m"
+    pub time: Trigger,
+    #[serde(flatten)]
+    pub setting: AcSetting,
+}
+
+/// Gradual change of the setpoint by one degree at a time between `start` and `end`
+#[derive(Clone, Deserialize)]
+pub struct SleepCurve {
+    pub start: NaiveTime,
+    pub end: NaiveTime,
+    pub mode: AcMode,
+    #[serde(default = "AcSetting::default_fan")]
+    pub fan: FanSpeed,
+    pub from: u8,
+    pub to: u8,
+}
+
+impl SleepCurve {
+    fn steps(&self) -> Vec<AcStep> {
+        let span = (self.end - self.start).num_seconds().rem_euclid(24 * 3600);
+        let cnt = self.from.abs_diff(self.to);
+
+        (0..=cnt).map(|i| {
+            let temperature = if self.to >= self.from { self.from + i } else { self.from - i };
+            let offset = if cnt == 0 { 0 } else { span * i64::from(i) / i64::from(cnt) };
+
+            AcStep {
+                time: Trigger::at(self.start + chrono::Duration::seconds(offset)),
+                setting: AcSetting {
+                    on: true,
+                    mode: self.mode,
+                    fan: self.fan,
+                    temperature,
+                },
+            }
+        }).collect()
+    }
+}
+
+/// Daily program applied to an AC unit in any of the listed heating/cooling states
+#[derive(Clone, Deserialize)]
+pub struct AcProgram {
+    pub states: Vec<HcState>,
+    #[serde(default)]
+    pub steps: Vec<AcStep>,
+    pub sleep_curve: Option<SleepCurve>,
+    /// Applied when the room of the unit becomes occupied
+    pub occupied: Option<AcSetting>,
+    /// Applied when the room of the unit is left
+    pub vacant: Option<AcSetting>,
+}
+
+impl AcProgram {
+    fn all_steps(&self) -> Vec<AcStep> {
+        let mut steps = self.steps.clone();
+        if let Some(sleep_curve) = &self.sleep_curve {
+            steps.extend(sleep_curve.steps());
+        }
+        steps
+    }
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(default)]
+pub struct AcConfig {
+    pub units: Vec<AcUnit>,
+}
+
+impl AcConfig {
+    /// A heating/cooling state may be covered by one program of a unit at most. Units are
+    /// left alone in states without a program, e.g. cooling-only units while heating
+    pub fn validate(&self) -> Result<(), String> {
+        let states = [HcState::HeatingActive, HcState::HeatingPassive, HcState::CoolingPassive, HcState::CoolingActive];
+        for unit in &self.units {
+            for state in states {
+                if unit.programs.iter().filter(|p| p.states.contains(&state)).count() > 1 {
+                    return Err(format!("AC unit {} has more than one program for state {:?}", unit.resource, state));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Default for AcConfig {
+    fn default() -> Self {
+        Self {
+            units: AcUnit::default_units(),
+        }
+    }
+}
+
+#[derive(Clone, Deserialize)]
+pub struct AcUnit {
+    pub resource: String,
+    pub programs: Vec<AcProgram>,
+}
+
+impl AcUnit {
+    /// The unit works as a heat pump in shoulder seasons and cools in summer, following the
+    /// occupancy of the room with (occupied, vacant) settings. Otherwise it is only switched
+    /// off when the room is left
+    fn with_occupancy(resource: &str, off: AcSetting, heating: (AcSetting, AcSetting), cooling: (AcSetting, AcSetting)) -> Self {
+        let program = |states, (occupied, vacant)| AcProgram {
+            states,
+            steps: Vec::new(),
+            sleep_curve: None,
+            occupied,
+            vacant: Some(vacant),
+        };
+
+        Self {
+            resource: resource.to_string(),
+            programs: vec![
+                program(vec![HcState::HeatingActive, HcState::CoolingPassive], (None, off)),
+                program(vec![HcState::HeatingPassive], (Some(heating.0), heating.1)),
+                program(vec![HcState::CoolingActive], (Some(cooling.0), cooling.1)),
+            ],
+        }
+    }
+
+    fn default_units() -> Vec<Self> {
+        let off = AcSetting::new(false, AcMode::Cool, 27);
+        let heating = (AcSetting::new(true, AcMode::Heat, 21), AcSetting::new(true, AcMode::Heat, 19));
+        let cooling = (AcSetting::new(true, AcMode::Cool, 26), AcSetting::new(true, AcMode::Cool, 28));
+
+        ["bac", "dac", "lac", "oac"].into_iter()
+            .map(|rsrc| Self::with_occupancy(rsrc, off, heating, cooling))
+            .collect()
+    }
+}
 
 pub struct Ac {
+    cp: CronProcessor,
     hvac_state: Arc<HvacState>,
+    units: Vec<AcUnit>,
+    twilight: Arc<web::Twilight>,
+    occupancy: Arc<Occupancy>,
 }
 
 impl Ac {
-    pub fn new(hvac_state: Arc<HvacState>) -> Self {
+    pub fn new(cp: CronProcessor, hvac_state: Arc<HvacState>, config: AcConfig, twilight: Arc<web::Twilight>, occupancy: Arc<Occupancy>) -> Self {
+        for unit in &config.units {
+            let follows = unit.programs.iter().any(|p| p.occupied.is_some() || p.vacant.is_some());
+            if follows && occupancy.room(&unit.resource).is_none() {
+                log::warn!(actuator = "ac", resource = unit.resource.as_str(); "Unit is not in any room, occupancy settings are not applied");
+            }
+        }
+
         Self {
+            cp,
             hvac_state,
+            units: config.units,
+            twilight,
+            occupancy,
         }
     }
 
-    async fn get_action_list(&self) -> Vec<Action> {
-        let mut actions = Vec::new();
-        
-        match self.hvac_state.get_state().await {
-            HcState::HeatingActive | HcState::HeatingPassive | HcState::CoolingPassive => {
-                let mut evening_action_list = Vec::new();
-                evening_action_list.push(("bac", (false, 'a', 27)));
-                evening_action_list.push(("dac", (false, 'a', 27)));
-                evening_action_list.push(("lac", (false, 'a', 27)));
-                evening_action_list.push(("oac", (false, 'a', 27)));
+    pub async fn get_action_list(&self, after: SystemTime) -> Vec<Action> {
+        let state = self.hvac_state.get_state().await;
 
-                actions.push(Action::new(
-                    CronProcessor::time_to_timestamp(NaiveTime::from_hms_opt(22, 0, 0).unwrap()),
-                    async move {
-                        CronProcessor::run_action(&evening_action_list, |r, v| async move {Self::set_ac(r, v).await}, None).await
+        // Units sharing the same time are handled by a single action
+        let mut action_lists: BTreeMap<SystemTime, Vec<(String, AcSetting)>> = BTreeMap::new();
+        for unit in &self.units {
+            let program = unit.programs.iter().find(|p| p.states.contains(&state));
+            if let Some(program) = program {
+                for step in program.all_steps() {
+                    match step.time.next_after(after, &self.twilight).await {
+                        Ok(time) => action_lists.entry(time).or_default().push((unit.resource.clone(), step.setting)),
+                        Err(e) => log::warn!(actuator = "ac", resource = unit.resource.as_str(); "Skipping step at {}: {}", step.time, e),
                     }
-                ));
-            },
-            HcState::CoolingActive => {
-                let mut morning_action_list = Vec::new();
-                morning_action_list.push(("bac", (true, 'a', 26)));
-                morning_action_list.push(("dac", (true, 'a', 26)));
-                morning_action_list.push(("lac", (true, 'a', 26)));
-                morning_action_list.push(("oac", (true, 'a', 26)));
-                
-                let mut evening_action_list = Vec::new();
-                evening_action_list.push(("bac", (true, 'a', 26)));
-                evening_action_list.push(("dac", (true, 'a', 28)));
-                evening_action_list.push(("lac", (true, 'a', 28)));
-                evening_action_list.push(("oac", (true, 'a', 28)));
-
-                actions.push(Action::new(
-                    CronProcessor::time_to_timestamp(NaiveTime::from_hms_opt(7, 0, 0).unwrap()),
-                    async move {
-                        CronProcessor::run_action(&morning_action_list, |r, v| async move {Self::set_ac(r, v).await}, None).await
+                }
+                for change in self.occupancy.changes(&unit.resource, after).await {
+                    let setting = if change.occupied { program.occupied } else { program.vacant };
+                    if let Some(setting) = setting {
+                        action_lists.entry(change.time).or_default().push((unit.resource.clone(), setting));
                     }
-                ));
-                actions.push(Action::new(
-                    CronProcessor::time_to_timestamp(NaiveTime::from_hms_opt(22, 0, 0).unwrap()),
+                }
+            }
+        }
+
+        action_lists.into_iter()
+            .map(|(time, action_list)| {
+                let cp = self.cp.clone();
+                Action::new(
+                    time,
+                    Description::new("ac").targets(&action_list),
                     async move {
-                        CronProcessor::run_action(&evening_action_list, |r, v| async move {Self::set_ac(r, v).await}, None).await
+                        let action_list: Vec<_> = action_list.iter().map(|(r, s)| (r.as_str(), *s)).collect();
+                        cp.run_action(&action_list, |r, v| async move {Self::set_ac(r, v).await}, None).await
                     }
-                ));
-            },
-        }
+                )
+            })
+            .collect()
+    }
 
-        actions
+    pub fn resources(config: &AcConfig) -> Vec<String> {
+        config.units.iter().map(|u| u.resource.clone()).collect()
     }
 
-    async fn set_ac(rsrc: &str, target: (bool, char, u8)) -> Result<(), String> {
-        let payload = [
-                ("o", ciborium::value::Value::Bool(target.0)),
-                ("f", ciborium::value::Value::Integer((target.1 as u8).try_into().unwrap())),
-                ("t", ciborium::value::Value::Integer(target.2.try_into().unwrap())),
-                ("m", ciborium::value::Value::Integer(('c' as u8).try_into().unwrap())),
-        ];
-        let payload = CborMap::from_slice(&payload);
+    pub async fn set_ac(rsrc: &str, target: AcSetting) -> Result<(), String> {
+        let payload = AcCommand::new(target.on, target.mode.as_char(), target.fan.as_char(), target.temperature)?;
 
-        basic::set_actuator(rsrc, payload).await
+        basic::set_actuator(rsrc, &payload).await
     }
 
     pub async fn process(&self) {
-        let cp = CronProcessor::new();
-
-        cp.process(
-            || async { self.get_action_list().await },
+        self.cp.process_with_reschedule(
+            "ac",
+            |after| async move { self.get_action_list(after).await },
+            Some(self.hvac_state.subscribe()),
         ).await;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(programs: &str) -> AcConfig {
+        serde_json::from_str(&format!(r#"{{"units": [{{"resource": "oac", "programs": {}}}]}}"#, programs)).unwrap()
+    }
+
+    #[test]
+    fn validate() {
+        assert!(AcConfig::default().validate().is_ok());
+
+        let cooling_only = config(r#"[{"states": ["CoolingActive"], "occupied": {"on": true, "mode": "cool", "temperature": 26}}]"#);
+        assert!(cooling_only.validate().is_ok());
+
+        let ambiguous = config(r#"[{"states": ["CoolingActive"]}, {"states": ["CoolingPassive", "CoolingActive"]}]"#);
+        assert!(ambiguous.validate().is_err());
+    }
+
+    #[test]
+    fn heat_pump_in_shoulder_season() {
+        for unit in AcUnit::default_units() {
+            let program = unit.programs.iter().find(|p| p.states.contains(&HcState::HeatingPassive)).unwrap();
+            assert_eq!(program.occupied.map(|s| (s.on, s.mode)), Some((true, AcMode::Heat)));
+        }
+    }
+}
@@ -1,65 +1,299 @@
 use chrono::prelude::*;
 use futures::prelude::*;
+use serde::Serialize;
 use std::boxed::Box;
 use std::pin::Pin;
+use std::sync::Arc;
 use std::time::{Duration, SystemTime};
+use tokio::sync::{broadcast, watch};
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::actuators::fade::{Fade, Interpolate};
+use crate::metrics;
+use crate::notify::{Event, EventKind, Notifier};
+use crate::state::{Overrides, Schedules};
+use crate::timezone::{self, DstPolicy};
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Target {
+    pub resource: String,
+    pub value: serde_json::Value,
+}
+
+/// What an action is going to do, readable without running it
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Description {
+    pub actuator: &'static str,
+    pub targets: Vec<Target>,
+    /// Checked when the action runs and may cancel it
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub conditions: Vec<String>,
+}
+
+impl Description {
+    pub fn new(actuator: &'static str) -> Self {
+        Self {
+            actuator,
+            targets: Vec::new(),
+            conditions: Vec::new(),
+        }
+    }
+
+    pub fn targets<R: AsRef<str>, C: Serialize>(mut self, resources: &[(R, C)]) -> Self {
+        self.targets.extend(resources.iter().map(|(rsrc, value)| Target {
+            resource: rsrc.as_ref().to_string(),
+            value: serde_json::to_value(value).unwrap_or_default(),
+        }));
+        self
+    }
+
+    pub fn condition(mut self, condition: &str) -> Self {
+        self.conditions.push(condition.to_string());
+        self
+    }
+}
+
+/// Action planned at a given time
+#[derive(Clone, Debug, Serialize)]
+pub struct Planned {
+    pub time: DateTime<FixedOffset>,
+    #[serde(flatten)]
+    pub description: Description,
+}
 
 pub struct Action
 {
     time: SystemTime,
+    description: Description,
     function: Pin<Box<dyn Future<Output=()> + Send>>,
+    finish: Option<Pin<Box<dyn Future<Output=()> + Send>>>,
 }
 
 impl Action
 {
-    pub fn new(time: SystemTime, function: impl Future<Output=()> + Send + 'static) -> Self
+    pub fn new(time: SystemTime, description: Description, function: impl Future<Output=()> + Send + 'static) -> Self
     {
         Action {
             time,
+            description,
             function: Box::pin(function),
+            finish: None,
+        }
+    }
+
+    /// Long running actions, e.g. fades, are stopped when the actions are built again.
+    /// `finish` runs instead, e.g. to set the targets right away
+    pub fn interruptible(mut self, finish: impl Future<Output=()> + Send + 'static) -> Self {
+        self.finish = Some(Box::pin(finish));
+        self
+    }
+
+    pub fn plan(&self) -> Planned {
+        Planned {
+            time: timezone::from_system(self.time),
+            description: self.description.clone(),
         }
     }
 }
 
-pub struct CronProcessor ();
+#[derive(Clone)]
+pub struct CronProcessor {
+    overrides: Arc<Overrides>,
+    notifier: Arc<Notifier>,
+    schedules: Arc<Schedules>,
+}
 
 impl CronProcessor {
-    pub fn new() -> Self {
-        CronProcessor {}
+    pub fn new(overrides: Arc<Overrides>, notifier: Arc<Notifier>, schedules: Arc<Schedules>) -> Self {
+        CronProcessor {
+            overrides,
+            notifier,
+            schedules,
+        }
+    }
+
+    pub async fn process<FG, FGFut>(&self, name: &str, get_actions: FG)
+        where
+        FG: Fn(SystemTime) -> FGFut,
+        FGFut: Future<Output = Vec<Action>>,
+    {
+        self.process_with_reschedule(name, get_actions, None::<watch::Receiver<()>>).await
     }
 
-    pub async fn process<FG, FGFut>(&self, get_actions: FG)
+    /// Like process, but the pending action is dropped and the action list is built again
+    /// as soon as the value watched by `reschedule` changes.
+    pub async fn process_with_reschedule<FG, FGFut, T>(&self, name: &str, get_actions: FG, mut reschedule: Option<watch::Receiver<T>>)
         where
-        FG: Fn() -> FGFut,
+        FG: Fn(SystemTime) -> FGFut,
         FGFut: Future<Output = Vec<Action>>,
     {
+        let mut triggers = self.schedules.subscribe_triggers();
+        let mut refreshes = self.schedules.subscribe_refreshes();
+        // Actions up to this time are done, as well as the listed ones run ahead of time on
+        // request. Other ones which are already due run right away, e.g. changes caused by a refresh
+        let mut done_until = SystemTime::now();
+        let mut done: Vec<(SystemTime, Description)> = Vec::new();
+
         loop {
-            let actions = get_actions().await;
+            if let Some(reschedule) = reschedule.as_mut() {
+                // Actions are built from the current value. Changes while building wake the loop again
+                reschedule.borrow_and_update();
+            }
+            let actions = get_actions(done_until).await;
+            done.retain(|(time, _)| *time > done_until);
 
             {
-                let mut next_action: Option<Action> = None;
-                let now = SystemTime::now();
-                for action in actions {
-                    if action.time <= now {
-                        continue;
+                let is_done = |action: &Action| done.iter().any(|(time, description)| *time == action.time && *description == action.description);
+                // An action run ahead of time is only waited for, so later ones of its kind are built
+                let next_action = actions.into_iter()
+                    .filter(|action| action.time > done_until)
+                    .min_by_key(|action| (action.time, is_done(action)));
+                
+                let Some(next_action) = next_action else {
+                    // E.g. no program for the current state. Nothing to do until something changes
+                    log::info!(schedule = name; "No actions planned, waiting for changes");
+                    self.schedules.clear_next(name);
+                    tokio::select! {
+                        _ = Self::changed(&mut reschedule) => {
+                            log::info!(schedule = name; "Rescheduling actions");
+                            done_until = done_until.max(SystemTime::now());
+                        },
+                        _ = Self::refreshed(&mut refreshes) => log::info!(schedule = name; "Rescheduling actions on request"),
                     }
-                    if next_action.is_none() || action.time < next_action.as_ref().unwrap().time {
-                        next_action = Some(action);
+                    continue;
+                };
+                // Actions already due are not delayed
+                let sleep_time = next_action.time.duration_since(SystemTime::now()).unwrap_or_default();
+                let planned = next_action.plan();
+                if is_done(&next_action) {
+                    tokio::select! {
+                        _ = tokio::time::sleep(sleep_time) => done_until = next_action.time,
+                        _ = Self::changed(&mut reschedule) => {
+                            log::info!(schedule = name; "Rescheduling actions");
+                            done_until = done_until.max(SystemTime::now());
+                        },
+                        _ = Self::refreshed(&mut refreshes) => log::info!(schedule = name; "Rescheduling actions on request"),
                     }
+                    continue;
                 }
-                
-                let next_action = next_action.unwrap(); // TODO: handle errors
-                let now = SystemTime::now();
-                let sleep_time = next_action.time.duration_since(now).map_err(|e| e.to_string()).unwrap(); // TODO: Handle errors
-                println!("Sleeping for {:?}", sleep_time);
-                tokio::time::sleep(sleep_time).await;
+                if sleep_time.is_zero() {
+                    // E.g. planned while a previous action was still running
+                    log::info!(schedule = name, action:% = planned.time, actuator = planned.description.actuator;
+                               "Running overdue action {}", Self::summary(&planned.description));
+                }
+                log::debug!(schedule = name, action:% = planned.time, actuator = planned.description.actuator;
+                            "Sleeping for {:?} before setting {}", sleep_time, Self::summary(&planned.description));
+                self.schedules.set_next(name, planned);
+                tokio::select! {
+                    _ = tokio::time::sleep(sleep_time) => {
+                        done_until = next_action.time;
+                        if Self::run(name, next_action, &mut reschedule, &mut refreshes).await {
+                            done_until = done_until.max(SystemTime::now());
+                        }
+                    },
+                    // Actions the new value would have planned in the past are not run
+                    _ = Self::changed(&mut reschedule) => {
+                        log::info!(schedule = name; "Rescheduling actions");
+                        done_until = done_until.max(SystemTime::now());
+                    },
+                    _ = Self::refreshed(&mut refreshes) => log::info!(schedule = name; "Rescheduling actions on request"),
+                    _ = Self::triggered(&mut triggers, name) => {
+                        log::info!(schedule = name, action:% = timezone::from_system(next_action.time); "Running action now on request");
+                        // Actions due before the triggered one are still run on time
+                        done.push((next_action.time, next_action.description.clone()));
+                        if Self::run(name, next_action, &mut reschedule, &mut refreshes).await {
+                            done_until = done_until.max(SystemTime::now());
+                        }
+                    },
+                }
+            }
+        }
+    }
+
+    /// Runs the action to completion, unless it is interruptible and actions are to be built
+    /// again. Returns true if interrupted by a change of the value watched by `reschedule`
+    async fn run<T>(name: &str, action: Action, reschedule: &mut Option<watch::Receiver<T>>, refreshes: &mut broadcast::Receiver<()>) -> bool {
+        let Some(finish) = action.finish else {
+            action.function.await;
+            return false;
+        };
+
+        let rescheduled = tokio::select! {
+            _ = action.function => return false,
+            _ = Self::changed(reschedule) => {
+                log::info!(schedule = name; "Interrupting action to reschedule actions");
+                true
+            },
+            _ = Self::refreshed(refreshes) => {
+                log::info!(schedule = name; "Interrupting action to reschedule actions on request");
+                false
+            },
+        };
+        finish.await;
+        rescheduled
+    }
+
+    fn summary(description: &Description) -> String {
+        description.targets.iter()
+            .map(|t| format!("{}={}", t.resource, t.value))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    /// Actions built by `get_actions` for the next `days` days, without executing them.
+    /// Each call of `get_actions` yields the next occurrence of every action, so it is
+    /// called again right after the earliest one until the period is covered.
+    pub async fn preview<FG, FGFut>(get_actions: FG, days: u32) -> Vec<Planned>
+        where
+        FG: Fn(SystemTime) -> FGFut,
+        FGFut: Future<Output = Vec<Action>>,
+    {
+        let until = SystemTime::now() + Duration::from_secs(u64::from(days) * 24 * 3600);
+        let mut after = SystemTime::now();
+        let mut planned = Vec::new();
+        loop {
+            let actions = get_actions(after).await;
+            let next = actions.iter().map(|a| a.time).filter(|t| *t > after).min();
+            match next {
+                Some(next) if next <= until => {
+                    planned.extend(actions.iter().filter(|a| a.time == next).map(Action::plan));
+                    after = next;
+                },
+                _ => break,
+            }
+        }
+        planned
+    }
 
-                next_action.function.await;
+    async fn triggered(triggers: &mut broadcast::Receiver<String>, name: &str) {
+        loop {
+            match triggers.recv().await {
+                Ok(schedule) if schedule == name => return,
+                Ok(_) | Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => future::pending::<()>().await,
             }
         }
     }
 
-    pub async fn run_action<'a, F, C, Fut>(resources: &[(&'a str, C)],
+    async fn refreshed(refreshes: &mut broadcast::Receiver<()>) {
+        match refreshes.recv().await {
+            // A missed refresh still means the actions should be built again
+            Ok(()) | Err(RecvError::Lagged(_)) => (),
+            Err(RecvError::Closed) => future::pending::<()>().await,
+        }
+    }
+
+    async fn changed<T>(reschedule: &mut Option<watch::Receiver<T>>) {
+        if let Some(reschedule) = reschedule {
+            if reschedule.changed().await.is_ok() {
+                return;
+            }
+        }
+        future::pending::<()>().await
+    }
+
+    pub async fn run_action<'a, F, C, Fut>(&self,
+                                           resources: &[(&'a str, C)],
                                            action: F,
                                            num_tries: Option<u32>)
         where F: Fn(&'a str, C) -> Fut,
@@ -69,38 +303,208 @@ impl CronProcessor {
     {
         // TODO: spawn threads for each of the resources to manage them in parallel?
         for rsrc in resources {
+            if self.overrides.is_overridden(rsrc.0).await {
+                log::info!(resource = rsrc.0; "Skipping action for manually overridden resource");
+                continue;
+            }
+
             let mut loop_cnt = num_tries.unwrap_or(4);
             if loop_cnt == 0 { loop_cnt = 1 } // TODO: Infinite number of retries for 0?
 
             loop {
                 let result = action(rsrc.0, rsrc.1).await;
                 match result {
-                    Ok(_) => break,
-                    Err(e) => {
-                        println!("Error handling action for resource {}: {}", rsrc.0, e); // TODO: Better error handlig
+                    Ok(_) => {
+                        metrics::action(rsrc.0, true);
+                        self.schedules.report(rsrc.0, &result);
+                        break;
+                    },
+                    Err(ref e) => {
+                        log::warn!(resource = rsrc.0; "Error handling action: {}", e); // TODO: Better error handlig
                         loop_cnt -= 1;
                         if loop_cnt == 0 {
+                            metrics::action(rsrc.0, false);
+                            self.schedules.report(rsrc.0, &result);
+                            self.notifier.notify(Event::new(
+                                    EventKind::ActionFailure,
+                                    format!("Action for {} failed", rsrc.0),
+                                    format!("Giving up on {} after {} tries: {}", rsrc.0, num_tries.unwrap_or(4).max(1), e),
+                            )).await;
                             break;
                         }
 
+                        metrics::action_retry(rsrc.0);
                         tokio::time::sleep(Duration::from_secs(15)).await;
                     }
                 }
             }
         }
     }
-    
-    pub fn time_to_timestamp(time: NaiveTime) -> SystemTime {
-        let now = Local::now();
-        let today = now.date_naive();
-        let tomorrow = today.succ_opt().unwrap();
-        let today_time = today.and_time(time);
-        let tomorrow_time = tomorrow.and_time(time);
-        let today_time_with_tz = today_time.and_local_timezone(Local).earliest().unwrap(); // TODO: handle gap
-
-        let target_time = if now > today_time_with_tz { tomorrow_time } else { today_time };
-        target_time.and_local_timezone(Local)
-            .earliest().unwrap() // TODO: handle gap
-            .try_into().unwrap()
+
+    /// Like run_action, but resources change gradually from their state read by `read`.
+    /// Intermediate values are sent on every step of the fade and the target is set at its
+    /// end by run_action. Resources overridden during the fade are left alone
+    pub async fn run_fade<'a, F, R, C, Fut, RFut>(&self,
+                                                 resources: &[(&'a str, C)],
+                                                 fade: Fade,
+                                                 read: R,
+                                                 action: F,
+                                                 num_tries: Option<u32>)
+        where F: Fn(&'a str, C) -> Fut,
+              R: Fn(&'a str) -> RFut,
+              C: Interpolate,
+              Fut: futures::Future<Output = Result<(), String>>,
+              Fut: 'a,
+              RFut: futures::Future<Output = Result<C, String>>,
+    {
+        if !fade.is_instant() {
+            // Resource, start, target and the last value sent
+            let mut fading = Vec::new();
+            for (rsrc, target) in resources {
+                if self.overrides.is_overridden(rsrc).await {
+                    continue;
+                }
+                match read(rsrc).await {
+                    Ok(from) => fading.push((*rsrc, from, *target, from)),
+                    Err(e) => log::warn!(resource = *rsrc; "Cannot read state to fade from, setting target at the end: {}", e),
+                }
+            }
+
+            let (steps, interval) = fade.steps();
+            let start = tokio::time::Instant::now();
+            for step in 1..steps {
+                tokio::time::sleep_until(start + interval * step).await;
+                let progress = f64::from(step) / f64::from(steps);
+                for (rsrc, from, target, sent) in fading.iter_mut() {
+                    if self.overrides.is_overridden(rsrc).await {
+                        continue;
+                    }
+                    let value = C::interpolate(*from, *target, progress);
+                    if value == *sent {
+                        continue;
+                    }
+                    match action(rsrc, value).await {
+                        Ok(()) => *sent = value,
+                        Err(e) => log::debug!(resource = *rsrc; "Skipping step of fade: {}", e),
+                    }
+                }
+            }
+            tokio::time::sleep_until(start + interval * steps).await;
+        }
+
+        self.run_action(resources, action, num_tries).await
+    }
+
+    /// Next occurrence of the local time of day after `after`. On days the time is skipped
+    /// by a clock change it is shifted forward, when it is repeated the first one counts.
+    pub fn time_to_timestamp(time: NaiveTime, after: SystemTime) -> SystemTime {
+        let today = timezone::from_system(after).date_naive();
+        today.iter_days()
+            .flat_map(|day| timezone::resolve(day.and_time(time), DstPolicy::default()))
+            .find(|t| *t > after)
+            .expect("Every day has the time of day with the default DST policy")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use super::*;
+    use crate::notify::NotifyConfig;
+    use crate::state::OverrideConfig;
+
+    fn cron_processor() -> (CronProcessor, Arc<Schedules>) {
+        let notifier = Arc::new(Notifier::new(NotifyConfig::default(), None).unwrap());
+        let overrides = Arc::new(Overrides::new(OverrideConfig::default(), notifier.clone()));
+        let schedules = Arc::new(Schedules::new());
+        (CronProcessor::new(overrides, notifier, schedules.clone()), schedules)
+    }
+
+    fn record(log: &Arc<Mutex<Vec<String>>>, time: SystemTime, name: String) -> Action {
+        let log = log.clone();
+        Action::new(time, Description::new("test").targets(&[(name.as_str(), 0)]), async move {
+            log.lock().unwrap().push(name);
+        })
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn interrupted_fade_reaches_target() {
+        let (cp, _schedules) = cron_processor();
+        let sent = Arc::new(Mutex::new(Vec::new()));
+        let (reschedule, reschedule_rx) = watch::channel(());
+        let start = SystemTime::now() + Duration::from_secs(1);
+
+        let sent_for_actions = sent.clone();
+        let built = Arc::new(Mutex::new(false));
+        let task = tokio::spawn(async move {
+            let cp_for_actions = cp.clone();
+            cp.process_with_reschedule("test", |_| {
+                let mut actions = Vec::new();
+                // Planned once, as if the interrupted action were in the past
+                if !std::mem::replace(&mut *built.lock().unwrap(), true) {
+                    let write = |sent: Arc<Mutex<Vec<u16>>>| move |_: &str, value: u16| {
+                        sent.lock().unwrap().push(value);
+                        async { Ok(()) }
+                    };
+                    let (cp, finish_cp) = (cp_for_actions.clone(), cp_for_actions.clone());
+                    let (sent, finish_sent) = (sent_for_actions.clone(), sent_for_actions.clone());
+                    actions.push(Action::new(start, Description::new("test"), async move {
+                        cp.run_fade(&[("lr", 100)], Fade::new(100), |_| async { Ok(0) }, write(sent), None).await
+                    }).interruptible(async move {
+                        finish_cp.run_action(&[("lr", 100)], write(finish_sent), None).await
+                    }));
+                }
+                async move { actions }
+            }, Some(reschedule_rx)).await
+        });
+
+        tokio::time::sleep(Duration::from_secs(30)).await;
+        reschedule.send(()).unwrap();
+        tokio::time::sleep(Duration::from_secs(200)).await;
+        task.abort();
+
+        let sent = sent.lock().unwrap();
+        assert!(sent.len() > 2 && sent.len() < 10, "{:?}", sent);
+        assert!(sent[0] > 0 && sent[sent.len() - 2] < 50, "{:?}", sent);
+        assert_eq!(sent.last(), Some(&100));
+    }
+
+    async fn wait_planned(schedules: &Schedules) {
+        while !schedules.upcoming().contains_key("test") {
+            tokio::task::yield_now().await;
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn triggered_action_runs_once() {
+        let (cp, schedules) = cron_processor();
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let base = SystemTime::now();
+        let presence = Arc::new(Mutex::new(false));
+
+        // "x" every 10 seconds, "y" 5 seconds from now once something changed
+        let log_for_actions = log.clone();
+        let presence_for_actions = presence.clone();
+        let task = tokio::spawn(async move {
+            cp.process("test", |after| {
+                let secs = after.duration_since(base).unwrap_or_default().as_secs() / 10 * 10 + 10;
+                let mut actions = vec![record(&log_for_actions, base + Duration::from_secs(secs), format!("x{}", secs))];
+                if *presence_for_actions.lock().unwrap() {
+                    actions.push(record(&log_for_actions, base + Duration::from_secs(5), "y5".to_string()));
+                }
+                async move { actions }
+            }).await
+        });
+
+        wait_planned(&schedules).await;
+        schedules.trigger("test").unwrap();
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        *presence.lock().unwrap() = true;
+        schedules.refresh();
+        tokio::time::sleep(Duration::from_secs(60)).await;
+        task.abort();
+
+        assert_eq!(log.lock().unwrap()[..3], ["x10", "y5", "x20"]);
     }
 }
@@ -0,0 +1,101 @@
+use serde::Deserialize;
+use std::time::Duration;
+
+/// Gradual change of resources to their targets over `seconds`, with an intermediate
+/// command sent every `step_seconds` at most. Targets are set right away by default
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(default)]
+pub struct Fade {
+    pub seconds: u64,
+    pub step_seconds: u64,
+}
+
+impl Default for Fade {
+    fn default() -> Self {
+        Self {
+            seconds: 0,
+            step_seconds: 5,
+        }
+    }
+}
+
+impl Fade {
+    pub fn new(seconds: u64) -> Self {
+        Self {
+            seconds,
+            ..Default::default()
+        }
+    }
+
+    pub fn is_instant(&self) -> bool {
+        self.seconds == 0
+    }
+
+    /// Number of steps, the last one reaching the target, and the time between them
+    pub fn steps(&self) -> (u32, Duration) {
+        let steps = self.seconds.div_ceil(self.step_seconds.max(1)).max(1);
+        let steps = u32::try_from(steps).unwrap_or(u32::MAX);
+        (steps, Duration::from_secs(self.seconds) / steps)
+    }
+}
+
+/// Value which can be changed gradually
+pub trait Interpolate: Copy + PartialEq {
+    /// Value `progress` of the way from `from` to `to`, where progress goes from 0 to 1
+    fn interpolate(from: Self, to: Self, progress: f64) -> Self;
+}
+
+fn lerp(from: f64, to: f64, progress: f64) -> f64 {
+    from + (to - from) * progress
+}
+
+fn to_u16(value: f64) -> u16 {
+    value.round().clamp(0.0, f64::from(u16::MAX)) as u16
+}
+
+/// Shade positions
+impl Interpolate for u16 {
+    fn interpolate(from: Self, to: Self, progress: f64) -> Self {
+        to_u16(lerp(f64::from(from), f64::from(to), progress))
+    }
+}
+
+/// RGBW colors of LEDs. Duty cycles are proportional to emitted light, so colors are
+/// interpolated in the OKLab color space to change evenly to the eye. The white channel
+/// follows the lightness of the same space
+impl Interpolate for (u16, u16, u16, u16) {
+    fn interpolate(from: Self, to: Self, progress: f64) -> Self {
+        let from_lab = to_oklab([f64::from(from.0), f64::from(from.1), f64::from(from.2)]);
+        let to_lab = to_oklab([f64::from(to.0), f64::from(to.1), f64::from(to.2)]);
+        let lab = [0, 1, 2].map(|i| lerp(from_lab[i], to_lab[i], progress));
+        let [r, g, b] = from_oklab(lab);
+        let w = lerp(f64::from(from.3).cbrt(), f64::from(to.3).cbrt(), progress).powi(3);
+
+        (to_u16(r), to_u16(g), to_u16(b), to_u16(w))
+    }
+}
+
+/// Linear RGB to OKLab. The scale of the input does not matter for interpolation
+fn to_oklab([r, g, b]: [f64; 3]) -> [f64; 3] {
+    let l = (0.412_221_470_8 * r + 0.536_332_536_3 * g + 0.051_445_992_9 * b).cbrt();
+    let m = (0.211_903_498_2 * r + 0.680_699_545_1 * g + 0.107_396_956_6 * b).cbrt();
+    let s = (0.088_302_461_9 * r + 0.281_718_837_6 * g + 0.629_978_700_5 * b).cbrt();
+
+    [
+        0.210_454_255_3 * l + 0.793_617_785_0 * m - 0.004_072_046_8 * s,
+        1.977_998_495_1 * l - 2.428_592_205_0 * m + 0.450_593_709_9 * s,
+        0.025_904_037_1 * l + 0.782_771_766_2 * m - 0.808_675_766_0 * s,
+    ]
+}
+
+fn from_oklab([lightness, a, b]: [f64; 3]) -> [f64; 3] {
+    let l = (lightness + 0.396_337_777_4 * a + 0.215_803_757_3 * b).powi(3);
+    let m = (lightness - 0.105_561_345_8 * a - 0.063_854_172_8 * b).powi(3);
+    let s = (lightness - 0.089_484_177_5 * a - 1.291_485_548_0 * b).powi(3);
+
+    [
+        4.076_741_662_1 * l - 3.307_711_591_3 * m + 0.230_969_929_2 * s,
+        -1.268_438_004_6 * l + 2.609_757_401_1 * m - 0.341_319_396_5 * s,
+        -0.004_196_086_3 * l - 0.703_418_614_7 * m + 1.707_614_701_0 * s,
+    ]
+}
@@ -1,87 +1,108 @@
-use chrono::prelude::*;
 use rust_decimal::prelude::*;
+use serde::Deserialize;
+use std::collections::BTreeMap;
 use std::sync::Arc;
+use std::time::SystemTime;
 
-use crate::actuators::cron_processor::{Action, CronProcessor};
-use crate::coap;
-use crate::coap::{basic, CborMap};
-use crate::state::{HcState, HvacState};
+use crate::actuators::cron_processor::{Action, CronProcessor, Description};
+use crate::coap::{basic, FloorHeatingCommand};
+use crate::state::{HcState, HvacState, Occupancy};
+
+#[derive(Clone, Deserialize)]
+#[serde(default)]
+pub struct FloorHeatingConfig {
+    /// Setpoints while the rooms of the floors are occupied. Times come from the occupancy profiles
+    pub occupied: BTreeMap<String, Decimal>,
+    /// Setpoint low enough to disable heating, applied when the rooms are left
+    pub vacant: Decimal,
+}
+
+impl Default for FloorHeatingConfig {
+    fn default() -> Self {
+        Self {
+            occupied: BTreeMap::from([
+                ("gbrfh".to_string(), Decimal::new(240, 1)),
+                ("mbrfh".to_string(), Decimal::new(240, 1)),
+                ("kfh".to_string(), Decimal::new(245, 1)),
+            ]),
+            vacant: Decimal::new(175, 1),
+        }
+    }
+}
 
 pub struct FloorHeating {
+    cp: CronProcessor,
     hvac_state: Arc<HvacState>,
+    config: FloorHeatingConfig,
+    occupancy: Arc<Occupancy>,
 }
 
 impl FloorHeating {
-    pub fn new(hvac_state: Arc<HvacState>) -> Self {
+    pub const RESOURCES: [&'static str; 3] = ["gbrfh", "mbrfh", "kfh"];
+
+    pub fn new(cp: CronProcessor, hvac_state: Arc<HvacState>, config: FloorHeatingConfig, occupancy: Arc<Occupancy>) -> Self {
+        for rsrc in Self::RESOURCES {
+            if occupancy.room(rsrc).is_none() {
+                log::warn!(actuator = "floor_heating", resource = rsrc; "Resource is not in any room, it is not scheduled");
+            }
+        }
+
         FloorHeating {
+            cp,
             hvac_state,
+            config,
+            occupancy,
         }
     }
 
+    pub async fn get_action_list(&self, after: SystemTime) -> Vec<Action> {
+        let heating = match self.hvac_state.get_state().await {
+            HcState::HeatingActive | HcState::HeatingPassive => true,
+            HcState::CoolingActive | HcState::CoolingPassive => false,
+        };
 
-    async fn get_action_list(&self) -> Vec<Action> {
-        let disabled = Decimal::new(175, 1);
-        let mut actions = Vec::new();
-
-        match self.hvac_state.get_state().await {
-            HcState::HeatingActive | HcState::HeatingPassive => {
-                let mut morning_action_list = Vec::new();
-                morning_action_list.push(("gbrfh", Decimal::new(240, 1)));
-                morning_action_list.push(("mbrfh", Decimal::new(240, 1)));
-                morning_action_list.push(("kfh", Decimal::new(245, 1)));
-
-                let mut evening_action_list = Vec::new();
-                evening_action_list.push(("gbrfh", disabled));
-                evening_action_list.push(("mbrfh", disabled));
-                evening_action_list.push(("kfh", disabled));
-
-                actions.push(Action::new(
-                    CronProcessor::time_to_timestamp(NaiveTime::from_hms_opt(7, 0, 0).unwrap()),
-                    async move {
-                        CronProcessor::run_action(&morning_action_list, |r, v| async move {Self::set_temperature(r, &v).await}, None).await
-                    }
-                ));
-                actions.push(Action::new(
-                    CronProcessor::time_to_timestamp(NaiveTime::from_hms_opt(23, 0, 0).unwrap()),
-                    async move {
-                        CronProcessor::run_action(&evening_action_list, |r, v| async move {Self::set_temperature(r, &v).await}, None).await
-                    }
-                ));
-                println!("Heating");
-            },
-            HcState::CoolingActive | HcState::CoolingPassive => {
-                let mut evening_action_list = Vec::new();
-                evening_action_list.push(("gbrfh", disabled));
-                evening_action_list.push(("mbrfh", disabled));
-                evening_action_list.push(("kfh", disabled));
+        // Floors changing at the same time are handled by a single action
+        let mut action_lists: BTreeMap<SystemTime, Vec<(&'static str, Decimal)>> = BTreeMap::new();
+        for rsrc in Self::RESOURCES {
+            for change in self.occupancy.changes(rsrc, after).await {
+                let target = match change.occupied {
+                    // Floors are not heated when cooling, only the setpoint left from heating is reset
+                    true if heating => self.config.occupied.get(rsrc).copied(),
+                    true => None,
+                    false => Some(self.config.vacant),
+                };
+                if let Some(target) = target {
+                    action_lists.entry(change.time).or_default().push((rsrc, target));
+                }
+            }
+        }
+        log::info!(actuator = "floor_heating"; "{}", if heating { "Heating" } else { "Cooling" });
 
-                actions.push(Action::new(
-                    CronProcessor::time_to_timestamp(NaiveTime::from_hms_opt(23, 0, 0).unwrap()),
+        action_lists.into_iter()
+            .map(|(time, action_list)| {
+                let cp = self.cp.clone();
+                Action::new(
+                    time,
+                    Description::new("floor_heating").targets(&action_list),
                     async move {
-                        CronProcessor::run_action(&evening_action_list, |r, v| async move {Self::set_temperature(r, &v).await}, None).await
+                        cp.run_action(&action_list, |r, v| async move {Self::set_temperature(r, &v).await}, None).await
                     }
-                ));
-                println!("Cooling");
-            },
-        }
-
-        actions
+                )
+            })
+            .collect()
     }
 
     pub async fn process(&self) {
-        let cp = CronProcessor::new();
-
-        cp.process(
-            || async { self.get_action_list().await }
+        self.cp.process_with_reschedule(
+            "floor_heating",
+            |after| async move { self.get_action_list(after).await },
+            Some(self.hvac_state.subscribe()),
         ).await;
     }
 
-    async fn set_temperature(rsrc: &str, target: &Decimal) -> Result<(), String> {
-        let payload = [
-                ("s", coap::CborParser::from_decimal(target).map_err(|e| e.to_string())?),
-        ];
-        let payload = CborMap::from_slice(&payload);
+    pub async fn set_temperature(rsrc: &str, target: &Decimal) -> Result<(), String> {
+        let payload = FloorHeatingCommand::new(*target)?;
 
-        basic::set_actuator(rsrc, payload).await
+        basic::set_actuator(rsrc, &payload).await
     }
 }
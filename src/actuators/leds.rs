@@ -1,98 +1,365 @@
 use chrono::prelude::*;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::ops::Range;
 use std::sync::Arc;
-use std::time::SystemTime;
+use std::time::{Duration, SystemTime};
 
-use crate::actuators::cron_processor::{Action, CronProcessor};
-use crate::coap::{basic, CborMap};
+use crate::actuators::cron_processor::{Action, CronProcessor, Description};
+use crate::actuators::{Fade, Trigger};
+use crate::coap::{basic, DeviceKind, DeviceStatus, LedCommand};
+use crate::state::Occupancy;
 use crate::web;
 
+/// Colors of lights changed at the same time
+type ActionLists = BTreeMap<SystemTime, Vec<(String, (u16, u16, u16, u16))>>;
+
+#[derive(Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct LedsConfig {
+    /// Color of lights while their rooms are occupied after dusk, e.g. {"ll": [200, 150, 80, 255]}
+    pub occupied: BTreeMap<String, (u16, u16, u16, u16)>,
+    pub night_light: NightLightConfig,
+    pub wake_up: WakeUpConfig,
+    /// Fade of scheduled changes of the lights
+    pub fade: Fade,
+}
+
+/// Lights imitating sunrise before getting up. Other changes of the lights due from the start
+/// until they are switched off are skipped
+#[derive(Clone, Deserialize)]
+#[serde(default)]
+pub struct WakeUpConfig {
+    pub resources: Vec<String>,
+    pub color: (u16, u16, u16, u16),
+    /// Start of brightening. The color is reached `fade_minutes` later
+    pub start: Trigger,
+    pub fade_minutes: u64,
+    pub off: Trigger,
+}
+
+impl Default for WakeUpConfig {
+    fn default() -> Self {
+        Self {
+            resources: Vec::new(),
+            color: (255, 170, 90, 255),
+            start: "06:30 on workdays".parse().unwrap(),
+            fade_minutes: 30,
+            off: "08:00 on workdays".parse().unwrap(),
+        }
+    }
+}
+
+/// Lights imitating moonlight through the night
+#[derive(Clone, Deserialize)]
+#[serde(default)]
+pub struct NightLightConfig {
+    pub resources: Vec<String>,
+    /// Color of the full moon high in the sky. It is dimmed as the moon wanes or sets
+    pub color: (u16, u16, u16, u16),
+    pub start: Trigger,
+    /// Lights are switched off at bedtime or at dawn, whichever comes first
+    pub bedtime: Option<Trigger>,
+    /// Interval of following the moon across the sky
+    pub step_minutes: u64,
+    /// Time of fading out before the lights are switched off
+    pub fade_minutes: u64,
+}
+
+impl Default for NightLightConfig {
+    fn default() -> Self {
+        Self {
+            resources: vec!["drl".to_string()],
+            color: (160, 180, 210, 0),
+            start: "civil_dusk".parse().unwrap(),
+            bedtime: None,
+            step_minutes: 15,
+            fade_minutes: 30,
+        }
+    }
+}
+
 pub struct Leds {
+    cp: CronProcessor,
     moon: Arc<web::Moon>,
+    twilight: Arc<web::Twilight>,
+    config: LedsConfig,
+    occupancy: Arc<Occupancy>,
 }
 
 impl Leds {
-    pub fn new(moon: Arc<web::Moon>) -> Self {
+    pub const RESOURCES: [&'static str; 4] = ["bbl", "bwl", "drl", "ll"];
+
+    pub fn new(cp: CronProcessor, moon: Arc<web::Moon>, twilight: Arc<web::Twilight>, config: LedsConfig, occupancy: Arc<Occupancy>) -> Self {
+        for rsrc in config.occupied.keys() {
+            if occupancy.room(rsrc).is_none() {
+                log::warn!(actuator = "leds", resource = rsrc.as_str(); "Light is not in any room, it is not switched on");
+            }
+        }
+
         Self {
+            cp,
             moon,
+            twilight,
+            config,
+            occupancy,
+        }
+    }
+
+    async fn is_dark(&self, at: SystemTime) -> bool {
+        let [morning, evening] = self.get_twilight_pair(at).await;
+        morning < evening
+    }
+
+    /// Lights switched on when their rooms get occupied in the dark or are occupied at dusk,
+    /// and switched off when the rooms are left
+    async fn get_occupancy_action_lists(&self, after: SystemTime, evening_time: SystemTime)
+        -> ActionLists
+    {
+        let mut action_lists: BTreeMap<SystemTime, Vec<_>> = BTreeMap::new();
+        for (rsrc, color) in &self.config.occupied {
+            for change in self.occupancy.changes(rsrc, after).await {
+                if !change.occupied {
+                    action_lists.entry(change.time).or_default().push((rsrc.clone(), (0, 0, 0, 0)));
+                } else if self.is_dark(change.time).await {
+                    action_lists.entry(change.time).or_default().push((rsrc.clone(), *color));
+                }
+            }
+            if self.occupancy.is_occupied(rsrc, evening_time).await == Some(true) {
+                action_lists.entry(evening_time).or_default().push((rsrc.clone(), *color));
+            }
         }
+        action_lists
     }
 
-    async fn get_twilight_pair() -> [SystemTime; 2]
+    async fn get_twilight_pair(&self, after: SystemTime) -> [SystemTime; 2]
     {
         // TODO: Align it to the time of the year
         // TODO: Reuse with shades?
-        let morning_datetime = CronProcessor::time_to_timestamp(NaiveTime::from_hms_opt(6, 30, 0).unwrap());
-        let evening_datetime = CronProcessor::time_to_timestamp(NaiveTime::from_hms_opt(20, 0, 0).unwrap());
+        let morning_datetime = CronProcessor::time_to_timestamp(NaiveTime::from_hms_opt(6, 30, 0).unwrap(), after);
+        let evening_datetime = CronProcessor::time_to_timestamp(NaiveTime::from_hms_opt(20, 0, 0).unwrap(), after);
 
-        web::Twilight::new().get_pair().await.or::<Result<[SystemTime; 2], String>>(
+        self.twilight.get_pair_after(after).await.or::<Result<[SystemTime; 2], String>>(
             Ok([morning_datetime.try_into().unwrap(),
                 evening_datetime.try_into().unwrap()]))
             .unwrap()
     }
 
-    async fn get_action_list(&self) -> Vec<Action> {
+    /// Start and end of the night light ending after `after`
+    async fn get_night(&self, after: SystemTime) -> Result<(SystemTime, SystemTime), String> {
+        let config = &self.config.night_light;
+        let dawn: Trigger = "civil_dawn".parse()?;
+
+        // The night may have started already
+        let mut start = config.start.next_after(after - Duration::from_secs(24 * 3600), &self.twilight).await?;
+        loop {
+            let mut end = dawn.next_after(start, &self.twilight).await?;
+            if let Some(bedtime) = &config.bedtime {
+                end = end.min(bedtime.next_after(start, &self.twilight).await?);
+            }
+            if end > after {
+                return Ok((start, end));
+            }
+            start = config.start.next_after(start, &self.twilight).await?;
+        }
+    }
+
+    /// Moonlight colors following the phase and the altitude of the moon, faded out before the end
+    async fn get_night_light_action_lists(&self, after: SystemTime)
+        -> ActionLists
+    {
+        let config = &self.config.night_light;
+        let mut action_lists = BTreeMap::new();
+        if config.resources.is_empty() {
+            return action_lists;
+        }
+
+        let (start, end) = match self.get_night(after).await {
+            Ok(night) => night,
+            Err(e) => {
+                log::warn!(actuator = "leds"; "Skipping night light: {}", e);
+                return action_lists;
+            },
+        };
+        let illumination = match self.moon.get_phase().await {
+            Ok(phase) => web::Moon::illumination(phase),
+            Err(e) => {
+                log::warn!(actuator = "leds"; "Skipping night light without moon phase: {}", e);
+                return action_lists;
+            },
+        };
+
+        let step = Duration::from_secs(config.step_minutes.max(1) * 60);
+        let fade = Duration::from_secs(config.fade_minutes * 60);
+        let mut time = start;
+        while time < end {
+            if time > after {
+                let altitude = web::Moon::altitude(time).to_radians().sin().max(0.0);
+                let remaining = end.duration_since(time).unwrap_or_default();
+                let fading = if remaining < fade { remaining.as_secs_f64() / fade.as_secs_f64() } else { 1.0 };
+                let factor = illumination * altitude * fading;
+                let scale = |c: u16| (f64::from(c) * factor).round() as u16;
+                let (r, g, b, w) = config.color;
+                let rgbw = (scale(r), scale(g), scale(b), scale(w));
+
+                action_lists.insert(time, config.resources.iter().map(|rsrc| (rsrc.clone(), rgbw)).collect());
+            }
+            time += step;
+        }
+        action_lists.insert(end, config.resources.iter().map(|rsrc| (rsrc.clone(), (0, 0, 0, 0))).collect());
+
+        action_lists
+    }
+
+    pub async fn get_action_list(&self, after: SystemTime) -> Vec<Action> {
         let mut actions = Vec::new();
-        
-        let mut morning_action_list = Vec::new();
-        morning_action_list.push(("bbl", (0, 0, 0, 0)));
-        morning_action_list.push(("bwl", (0, 0, 0, 0)));
-        morning_action_list.push(("drl", (0, 0, 0, 0)));
-        morning_action_list.push(("ll", (0, 0, 0, 0)));
-        
-        // TODO: Twilight time?
-        let mut rgbw = (0, 0, 0, 0);
-        let moon_phase = self.moon.get_phase().await;
-        if let Ok(moon_phase) = moon_phase {
-            let factor = 1.0 - ((0.5 - f64::try_from(moon_phase).unwrap()).abs() * 2.0);
-            rgbw.0 = (160_f64 * factor).round() as u16;
-            rgbw.1 = (180_f64 * factor).round() as u16;
-            rgbw.2 = (210_f64 * factor).round() as u16;
-        }
-
-        /*
-        let mut evening_action_list = Vec::new();
-        evening_action_list.push(("bbl", (0,0,0,0)));
-        evening_action_list.push(("bwl", (0,0,0,0)));
-        evening_action_list.push(("drl", rgbw));
-        evening_action_list.push(("ll", (0,0,0,0)));
-        */
-
-        let twilight_pair = Self::get_twilight_pair().await;
+
+        let morning_action_list = ["bbl", "bwl", "drl", "ll"].map(|rsrc| (rsrc.to_string(), (0, 0, 0, 0)));
+
+        let twilight_pair = self.get_twilight_pair(after).await;
         let morning_time = twilight_pair[0];
         let evening_time = twilight_pair[1];
 
-        actions.push(Action::new(
-            morning_time,
-            async move {
-                CronProcessor::run_action(&morning_action_list, |r, v| async move {Self::set_led(r, v).await}, None).await
-            }
-        ));
-        actions.push(Action::new(
-            evening_time,
+        actions.extend(self.get_wake_up_actions(after).await);
+
+        // Lists planned at the same time are merged into a single action
+        let mut action_lists = self.get_night_light_action_lists(after).await;
+        action_lists.entry(morning_time).or_default().extend(morning_action_list);
+        for (time, action_list) in self.get_occupancy_action_lists(after, evening_time).await {
+            action_lists.entry(time).or_default().extend(action_list);
+        }
+        if let Some(window) = self.get_wake_up_window(after).await {
+            Self::skip_during_wake_up(&mut action_lists, &self.config.wake_up.resources, window);
+        }
+
+        for (time, action_list) in action_lists {
+            actions.push(self.led_action(time, action_list, self.config.fade));
+        }
+
+        actions
+    }
+
+    fn led_action(&self, time: SystemTime, action_list: Vec<(String, (u16, u16, u16, u16))>, fade: Fade) -> Action {
+        let cp = self.cp.clone();
+        let targets = action_list.clone();
+        let action = Action::new(
+            time,
+            Description::new("leds").targets(&action_list),
             async move {
+                let action_list: Vec<_> = action_list.iter().map(|(r, c)| (r.as_str(), *c)).collect();
+                cp.run_fade(&action_list, fade, Self::get_led, |r, v| async move {Self::set_led(r, v).await}, None).await
             }
-        ));
+        );
+        if fade.is_instant() {
+            return action;
+        }
 
-        actions
+        // Lights are not left half way if the fade is interrupted
+        let cp = self.cp.clone();
+        action.interruptible(async move {
+            let targets: Vec<_> = targets.iter().map(|(r, c)| (r.as_str(), *c)).collect();
+            cp.run_action(&targets, |r, v| async move {Self::set_led(r, v).await}, None).await
+        })
+    }
+
+    /// Period from the next start of the wake-up light, or from `after` if it is on already,
+    /// until it is switched off
+    async fn get_wake_up_window(&self, after: SystemTime) -> Option<Range<SystemTime>> {
+        let config = &self.config.wake_up;
+        if config.resources.is_empty() {
+            return None;
+        }
+
+        let start = config.start.next_after(after, &self.twilight).await.ok()?;
+        let off = config.off.next_after(after, &self.twilight).await.ok()?;
+        Some(if start < off { start } else { after }..off)
+    }
+
+    fn skip_during_wake_up(action_lists: &mut ActionLists, resources: &[String], window: Range<SystemTime>) {
+        for (_, action_list) in action_lists.range_mut(window) {
+            action_list.retain(|(rsrc, _)| !resources.contains(rsrc));
+        }
+        action_lists.retain(|_, action_list| !action_list.is_empty());
     }
 
-    async fn set_led(rsrc: &str, target: (u16, u16, u16, u16)) -> Result<(), String> {
-        let payload = [
-                ("r", ciborium::value::Value::Integer(target.0.try_into().unwrap())),
-                ("g", ciborium::value::Value::Integer(target.1.try_into().unwrap())),
-                ("b", ciborium::value::Value::Integer(target.2.try_into().unwrap())),
-                ("w", ciborium::value::Value::Integer(target.3.try_into().unwrap())),
+    /// Lights brightened slowly to the wake-up color and switched off later
+    async fn get_wake_up_actions(&self, after: SystemTime) -> Vec<Action> {
+        let config = &self.config.wake_up;
+        let mut actions = Vec::new();
+        if config.resources.is_empty() {
+            return actions;
+        }
+
+        let steps = [
+            (&config.start, config.color, Fade::new(config.fade_minutes * 60)),
+            (&config.off, (0, 0, 0, 0), self.config.fade),
         ];
-        let payload = CborMap::from_slice(&payload);
+        for (trigger, color, fade) in steps {
+            match trigger.next_after(after, &self.twilight).await {
+                Ok(time) => {
+                    let action_list = config.resources.iter().map(|rsrc| (rsrc.clone(), color)).collect();
+                    actions.push(self.led_action(time, action_list, fade));
+                },
+                Err(e) => log::warn!(actuator = "leds"; "Skipping wake-up light at {}: {}", trigger, e),
+            }
+        }
+        actions
+    }
 
-        basic::set_actuator(rsrc, payload).await
+    pub async fn get_led(rsrc: &str) -> Result<(u16, u16, u16, u16), String> {
+        match DeviceStatus::read(DeviceKind::Leds, rsrc).await? {
+            DeviceStatus::Leds(status) => Ok((status.r, status.g, status.b, status.w)),
+            _ => Err(format!("Unexpected state of {}", rsrc)),
+        }
     }
 
-    pub async fn process(&self) {
-        let cp = CronProcessor::new();
+    pub async fn set_led(rsrc: &str, target: (u16, u16, u16, u16)) -> Result<(), String> {
+        let payload = LedCommand {
+            r: target.0,
+            g: target.1,
+            b: target.2,
+            w: target.3,
+        };
+
+        basic::set_actuator(rsrc, &payload).await
+    }
 
-        cp.process(
-            || async { self.get_action_list().await },
+    pub async fn process(&self) {
+        self.cp.process(
+            "leds",
+            |after| async move { self.get_action_list(after).await },
         ).await;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(minutes: u64) -> SystemTime {
+        SystemTime::UNIX_EPOCH + Duration::from_secs(minutes * 60)
+    }
+
+    fn off(resources: &[&str]) -> Vec<(String, (u16, u16, u16, u16))> {
+        resources.iter().map(|rsrc| (rsrc.to_string(), (0, 0, 0, 0))).collect()
+    }
+
+    #[test]
+    fn wake_up_not_interrupted() {
+        // Night light off before the wake-up light starts at 6:30, dawn and a change of the
+        // wake-up light only in the meantime, wake-up light off at 8:00
+        let mut action_lists = BTreeMap::from([
+            (at(360), off(&["drl"])),
+            (at(430), off(&["bbl", "bwl", "drl", "ll"])),
+            (at(450), off(&["bbl"])),
+            (at(480), off(&["bbl"])),
+        ]);
+        Leds::skip_during_wake_up(&mut action_lists, &["bbl".to_string()], at(390)..at(480));
+
+        assert_eq!(action_lists, BTreeMap::from([
+            (at(360), off(&["drl"])),
+            (at(430), off(&["bwl", "drl", "ll"])),
+            (at(480), off(&["bbl"])),
+        ]));
+    }
+}
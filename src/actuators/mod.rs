@@ -1,10 +1,28 @@
 pub mod cron_processor;
 mod ac;
+mod fade;
 mod floor_heating;
 mod leds;
 mod shades;
+mod transitions;
+mod trigger;
 
-pub use ac::Ac;
-pub use floor_heating::FloorHeating;
-pub use leds::Leds;
-pub use shades::Shades;
+pub use ac::{Ac, AcConfig, AcSetting};
+pub use fade::Fade;
+pub use floor_heating::{FloorHeating, FloorHeatingConfig};
+pub use leds::{Leds, LedsConfig};
+pub use shades::{Shades, ShadesConfig};
+pub use transitions::{TransitionHook, TransitionHooks};
+pub use trigger::{SolarEvent, Trigger};
+
+use crate::coap::DeviceKind;
+
+/// All resources managed by the actuators
+pub fn devices(ac: &AcConfig) -> Vec<(DeviceKind, String)> {
+    let mut devices = Vec::new();
+    devices.extend(Ac::resources(ac).into_iter().map(|r| (DeviceKind::Ac, r)));
+    devices.extend(FloorHeating::RESOURCES.iter().map(|r| (DeviceKind::FloorHeating, r.to_string())));
+    devices.extend(Leds::RESOURCES.iter().map(|r| (DeviceKind::Leds, r.to_string())));
+    devices.extend(Shades::RESOURCES.iter().map(|r| (DeviceKind::Shades, r.to_string())));
+    devices
+}
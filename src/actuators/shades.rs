@@ -1,126 +1,209 @@
 use chrono::prelude::*;
+use serde::Deserialize;
+use std::collections::BTreeMap;
 use std::sync::Arc;
 use std::time::{Duration, SystemTime};
 
-use crate::actuators::cron_processor::{Action, CronProcessor};
-use crate::coap::{basic, CborMap};
+use crate::actuators::cron_processor::{Action, CronProcessor, Description};
+use crate::actuators::{Fade, Trigger};
+use crate::coap::{basic, DeviceKind, DeviceStatus, ShadeCommand};
 use crate::state::{HcState, HvacState};
 use crate::web;
 
+/// Triggers moving the shades in the parts of the day
+#[derive(Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct ShadeTriggers {
+    pub morning: Option<Trigger>,
+    pub noon: Option<Trigger>,
+    pub evening: Option<Trigger>,
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(default)]
+pub struct ShadesConfig {
+    pub morning: Trigger,
+    /// Uncovering the windows when cooling
+    pub noon: Trigger,
+    pub evening: Trigger,
+    /// Triggers replacing the above for single resources, e.g. {"k": {"evening": "sunset"}}
+    pub resources: BTreeMap<String, ShadeTriggers>,
+    /// Fade of scheduled moves, e.g. {"seconds": 900} to close the shades gradually
+    pub fade: Fade,
+}
+
+impl Default for ShadesConfig {
+    fn default() -> Self {
+        Self {
+            morning: "civil_dawn".parse().unwrap(),
+            noon: Trigger::at(NaiveTime::from_hms_opt(12, 0, 0).unwrap()),
+            evening: "civil_dusk".parse().unwrap(),
+            resources: BTreeMap::new(),
+            fade: Fade::default(),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Phase {
+    Morning,
+    Noon,
+    Evening,
+}
+
+impl Phase {
+    /// Used if the trigger cannot be resolved, e.g. sun data is not available
+    fn fallback(&self) -> NaiveTime {
+        // TODO: Align it to the time of the year
+        match self {
+            Phase::Morning => NaiveTime::from_hms_opt(6, 30, 0).unwrap(),
+            Phase::Noon => NaiveTime::from_hms_opt(12, 0, 0).unwrap(),
+            Phase::Evening => NaiveTime::from_hms_opt(19, 0, 0).unwrap(),
+        }
+    }
+}
+
 pub struct Shades {
+    cp: CronProcessor,
     hvac_state: Arc<HvacState>,
     weather: Arc<web::Weather>,
+    twilight: Arc<web::Twilight>,
+    config: ShadesConfig,
 }
 
 impl Shades {
-    pub fn new(hvac_state: Arc<HvacState>,
+    pub const RESOURCES: [&'static str; 5] = ["lr", "dr1", "dr2", "dr3", "k"];
+
+    pub fn new(cp: CronProcessor,
+               hvac_state: Arc<HvacState>,
                weather: Arc<web::Weather>,
+               twilight: Arc<web::Twilight>,
+               config: ShadesConfig,
               ) -> Self {
         Self {
+            cp,
             hvac_state,
             weather,
+            twilight,
+            config,
         }
     }
 
-    async fn get_twilight_pair() -> [SystemTime; 2]
+    fn trigger(&self, phase: Phase, rsrc: &str) -> &Trigger {
+        let triggers = self.config.resources.get(rsrc);
+        let (specific, default) = match phase {
+            Phase::Morning => (triggers.and_then(|t| t.morning.as_ref()), &self.config.morning),
+            Phase::Noon => (triggers.and_then(|t| t.noon.as_ref()), &self.config.noon),
+            Phase::Evening => (triggers.and_then(|t| t.evening.as_ref()), &self.config.evening),
+        };
+        specific.unwrap_or(default)
+    }
+
+    /// Targets grouped by the next time their trigger for `phase` fires
+    async fn group(&self, phase: Phase, targets: &[(&'static str, u16)], after: SystemTime)
+        -> BTreeMap<SystemTime, Vec<(&'static str, u16)>>
     {
-        // TODO: Align it to the time of the year
-        let morning_datetime = CronProcessor::time_to_timestamp(NaiveTime::from_hms_opt(6, 30, 0).unwrap());
-        let evening_datetime = CronProcessor::time_to_timestamp(NaiveTime::from_hms_opt(19, 0, 0).unwrap());
+        let mut groups: BTreeMap<SystemTime, Vec<_>> = BTreeMap::new();
+        for target in targets {
+            let trigger = self.trigger(phase, target.0);
+            let time = match trigger.next_after(after, &self.twilight).await {
+                Ok(time) => time,
+                Err(e) => {
+                    log::warn!(actuator = "shades", resource = target.0; "Cannot resolve trigger {}, using fallback time: {}", trigger, e);
+                    CronProcessor::time_to_timestamp(phase.fallback(), after)
+                },
+            };
+            groups.entry(time).or_default().push(*target);
+        }
+        groups
+    }
+
+    fn move_action(&self, time: SystemTime, action_list: Vec<(&'static str, u16)>) -> Action {
+        let cp = self.cp.clone();
+        let fade = self.config.fade;
+        let targets = action_list.clone();
+        let action = Action::new(
+            time,
+            Description::new("shades").targets(&action_list),
+            async move {
+                cp.run_fade(&action_list, fade, Self::get_position, |r, v| async move {Self::move_shades(r, v).await}, None).await
+            }
+        );
+        self.finish_interrupted(action, fade, targets)
+    }
+
+    /// Shades are not left half way if the fade is interrupted
+    fn finish_interrupted(&self, action: Action, fade: Fade, action_list: Vec<(&'static str, u16)>) -> Action {
+        if fade.is_instant() {
+            return action;
+        }
 
-        web::Twilight::new().get_pair().await.or::<Result<[SystemTime; 2], String>>(
-            Ok([morning_datetime.try_into().unwrap(),
-                evening_datetime.try_into().unwrap()]))
-            .unwrap()
+        let cp = self.cp.clone();
+        action.interruptible(async move {
+            cp.run_action(&action_list, |r, v| async move {Self::move_shades(r, v).await}, None).await
+        })
     }
 
-    async fn get_action_list(&self) -> Vec<Action>
+    pub async fn get_action_list(&self, after: SystemTime) -> Vec<Action>
     {
         let mut actions = Vec::new();
         
         match self.hvac_state.get_state().await {
             HcState::HeatingActive | HcState::HeatingPassive => {
-                let mut morning_action_list = Vec::new();
-                morning_action_list.push(("lr", 0));
-                morning_action_list.push(("dr1", 0));
-                morning_action_list.push(("dr2", 0));
-                morning_action_list.push(("dr3", 0));
-                morning_action_list.push(("k", 0));
-
-                let mut evening_action_list = Vec::new();
-                evening_action_list.push(("lr", 256));
-                evening_action_list.push(("dr1", 256));
-                evening_action_list.push(("dr2", 256));
-                evening_action_list.push(("dr3", 256));
-                evening_action_list.push(("k", 256));
-
-                let twilight_pair =  Shades::get_twilight_pair().await;
-                let morning_time = twilight_pair[0];
-                let evening_time = twilight_pair[1];
-
-                actions.push(Action::new(
-                    morning_time,
-                    async move {
-                        CronProcessor::run_action(&morning_action_list, |r, v| async move {Self::move_shades(r, v).await}, None).await
-                    }
-                ));
-                actions.push(Action::new(
-                    evening_time,
-                    async move {
-                        CronProcessor::run_action(&evening_action_list, |r, v| async move {Self::move_shades(r, v).await}, None).await
-                    }
-                ));
-                println!("Heating");
+                let morning_action_list = [("lr", 0), ("dr1", 0), ("dr2", 0), ("dr3", 0), ("k", 0)];
+                let evening_action_list = [("lr", 256), ("dr1", 256), ("dr2", 256), ("dr3", 256), ("k", 256)];
+
+                for (time, action_list) in self.group(Phase::Morning, &morning_action_list, after).await {
+                    actions.push(self.move_action(time, action_list));
+                }
+                for (time, action_list) in self.group(Phase::Evening, &evening_action_list, after).await {
+                    actions.push(self.move_action(time, action_list));
+                }
+                log::info!(actuator = "shades"; "Heating");
             },
             HcState::CoolingActive | HcState::CoolingPassive => {
-                let mut morning_action_list = Vec::new();
-                morning_action_list.push(("lr", 128));
-                morning_action_list.push(("dr1", 128));
-                morning_action_list.push(("dr2", 128));
-                morning_action_list.push(("dr3", 128));
-                
-                let mut noon_action_list = Vec::new();
-                noon_action_list.push(("lr", 0));
-                noon_action_list.push(("dr1", 0));
-                noon_action_list.push(("dr2", 0));
-                noon_action_list.push(("dr3", 0));
-
-                let morning_weather = self.weather.clone();
-                let morning_time = Shades::get_twilight_pair().await[0];
-
-                actions.push(Action::new(
-                    morning_time,
-                    async move {
-                        let forecast = morning_weather.get_forecast(&Duration::from_secs(3600*6)).await;
-                        if forecast.is_ok() {
-                            let forecast = forecast.unwrap();
-                            if forecast.get_cloudiness() > 50 {
-                                println!("Expected morning clouds: {}. Skip shading", forecast.get_cloudiness());
-                                return ()
+                let morning_action_list = [("lr", 128), ("dr1", 128), ("dr2", 128), ("dr3", 128)];
+                let noon_action_list = [("lr", 0), ("dr1", 0), ("dr2", 0), ("dr3", 0)];
+
+                for (time, morning_action_list) in self.group(Phase::Morning, &morning_action_list, after).await {
+                    let morning_weather = self.weather.clone();
+                    let cp = self.cp.clone();
+                    let fade = self.config.fade;
+                    let targets = morning_action_list.clone();
+                    let action = Action::new(
+                        time,
+                        Description::new("shades").targets(&morning_action_list).condition("Forecast cloudiness at most 50%"),
+                        async move {
+                            let forecast = morning_weather.get_forecast(&Duration::from_secs(3600*6)).await;
+                            if forecast.is_ok() {
+                                let forecast = forecast.unwrap();
+                                if forecast.get_cloudiness() > 50 {
+                                    log::info!(actuator = "shades"; "Expected morning clouds: {}. Skip shading", forecast.get_cloudiness());
+                                    return ()
+                                }
                             }
-                        }
 
-                        CronProcessor::run_action(&morning_action_list, |r, v| async move {
-                            Self::move_shades(r, v).await
-                        }, None).await
-                    }
-                ));
-                actions.push(Action::new(
-                    CronProcessor::time_to_timestamp(NaiveTime::from_hms_opt(12, 0, 0).unwrap()),
-                    async move {
-                        CronProcessor::run_action(&noon_action_list, |r, v| async move {Self::move_shades(r, v).await}, None).await
-                    }
-                ));
-                println!("Cooling");
+                            cp.run_fade(&morning_action_list, fade, Self::get_position, |r, v| async move {
+                                Self::move_shades(r, v).await
+                            }, None).await
+                        }
+                    );
+                    actions.push(self.finish_interrupted(action, fade, targets));
+                }
+                for (time, action_list) in self.group(Phase::Noon, &noon_action_list, after).await {
+                    actions.push(self.move_action(time, action_list));
+                }
+                log::info!(actuator = "shades"; "Cooling");
             },
         }
 
         // Test action
         /*
+        let cp = self.cp.clone();
         actions.push(Action::new(
                 (Utc::now() + std::time::Duration::new(10, 0)).into(),
                 async move {
-                    CronProcessor::run_action(&[("lr", 0), ("dr1", 0)], |r, v| async move {Self::move_shades(r, v).await}, None).await
+                    cp.run_action(&[("lr", 0), ("dr1", 0)], |r, v| async move {Self::move_shades(r, v).await}, None).await
                 }
                 ));
         */
@@ -128,22 +211,26 @@ impl Shades {
         actions
     }
 
-    async fn move_shades(rsrc: &str, target: u16) -> Result<(), String> {
-        println!("{} {}", rsrc, target);
+    pub async fn get_position(rsrc: &str) -> Result<u16, String> {
+        match DeviceStatus::read(DeviceKind::Shades, rsrc).await? {
+            DeviceStatus::Shades(status) => Ok(status.position),
+            _ => Err(format!("Unexpected state of {}", rsrc)),
+        }
+    }
 
-        let payload = [
-                ("val", ciborium::value::Value::Integer(target.try_into().unwrap())),
-        ];
-        let payload = CborMap::from_slice(&payload);
+    pub async fn move_shades(rsrc: &str, target: u16) -> Result<(), String> {
+        log::debug!(actuator = "shades", resource = rsrc; "Moving to {}", target);
 
-        basic::set_actuator(rsrc, payload).await
+        let payload = ShadeCommand::new(target)?;
+
+        basic::set_actuator(rsrc, &payload).await
     }
 
     pub async fn process(&self) {
-        let cp = CronProcessor::new();
-
-        cp.process(
-            || async { self.get_action_list().await },
+        self.cp.process_with_reschedule(
+            "shades",
+            |after| async move { self.get_action_list(after).await },
+            Some(self.hvac_state.subscribe()),
         ).await;
     }
 }
@@ -0,0 +1,98 @@
+use rust_decimal::prelude::*;
+use serde::Deserialize;
+use std::sync::Arc;
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::actuators::cron_processor::CronProcessor;
+use crate::actuators::{Ac, AcSetting, FloorHeating, Leds, Shades};
+use crate::state::{HcState, HvacState, Transition};
+
+#[derive(Clone, Deserialize)]
+#[serde(tag = "actuator", rename_all = "snake_case")]
+pub enum TransitionAction {
+    Ac { resources: Vec<String>, setting: AcSetting },
+    FloorHeating { resources: Vec<String>, temperature: Decimal },
+    Leds { resources: Vec<String>, rgbw: (u16, u16, u16, u16) },
+    Shades { resources: Vec<String>, position: u16 },
+}
+
+/// Actions run when the heating/cooling state changes.
+/// A missing `from` or `to` matches any state.
+#[derive(Clone, Deserialize)]
+pub struct TransitionHook {
+    from: Option<HcState>,
+    to: Option<HcState>,
+    actions: Vec<TransitionAction>,
+}
+
+impl TransitionHook {
+    fn matches(&self, transition: &Transition) -> bool {
+        // The initial state is not a transition out of anything
+        let from = match transition.from {
+            Some(from) => from,
+            None => return false,
+        };
+
+        self.from.is_none_or(|s| s == from) && self.to.is_none_or(|s| s == transition.to)
+    }
+}
+
+pub struct TransitionHooks {
+    cp: CronProcessor,
+    hvac_state: Arc<HvacState>,
+    hooks: Vec<TransitionHook>,
+}
+
+impl TransitionHooks {
+    pub fn new(cp: CronProcessor, hvac_state: Arc<HvacState>, hooks: Vec<TransitionHook>) -> Self {
+        Self {
+            cp,
+            hvac_state,
+            hooks,
+        }
+    }
+
+    async fn run_action(&self, action: &TransitionAction) {
+        match action {
+            TransitionAction::Ac { resources, setting } => {
+                let list: Vec<_> = resources.iter().map(|r| (r.as_str(), *setting)).collect();
+                self.cp.run_action(&list, |r, v| async move {Ac::set_ac(r, v).await}, None).await
+            },
+            TransitionAction::FloorHeating { resources, temperature } => {
+                let list: Vec<_> = resources.iter().map(|r| (r.as_str(), *temperature)).collect();
+                self.cp.run_action(&list, |r, v| async move {FloorHeating::set_temperature(r, &v).await}, None).await
+            },
+            TransitionAction::Leds { resources, rgbw } => {
+                let list: Vec<_> = resources.iter().map(|r| (r.as_str(), *rgbw)).collect();
+                self.cp.run_action(&list, |r, v| async move {Leds::set_led(r, v).await}, None).await
+            },
+            TransitionAction::Shades { resources, position } => {
+                let list: Vec<_> = resources.iter().map(|r| (r.as_str(), *position)).collect();
+                self.cp.run_action(&list, |r, v| async move {Shades::move_shades(r, v).await}, None).await
+            },
+        }
+    }
+
+    pub async fn process(&self) {
+        let mut transitions = self.hvac_state.subscribe_transitions();
+
+        loop {
+            let transition = match transitions.recv().await {
+                Ok(transition) => transition,
+                Err(RecvError::Lagged(cnt)) => {
+                    log::warn!("Missed {} heating/cooling transitions", cnt);
+                    continue;
+                },
+                Err(RecvError::Closed) => return,
+            };
+
+            for hook in self.hooks.iter().filter(|h| h.matches(&transition)) {
+                log::info!("Running transition hook {:?} -> {:?} for {:?} -> {:?} at {:?}",
+                         hook.from, hook.to, transition.from, transition.to, transition.temperature);
+                for action in &hook.actions {
+                    self.run_action(action).await;
+                }
+            }
+        }
+    }
+}
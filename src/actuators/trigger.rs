@@ -0,0 +1,691 @@
+//! Times at which scheduled actions fire
+//!
+//! A trigger is a time specification optionally followed by day filters:
+//!
+//! - `07:30` - every day at the given local time
+//! - `every 15m` - every 15 minutes from midnight; `h` is accepted for hours
+//! - `civil_dusk - 15m` - offset from a solar event: `civil_dawn`, `sunrise`, `noon`, `sunset` or `civil_dusk`
+//! - `cron 0 7 * * 1-5` - cron expression: minute, hour, day of month, month and day of week
+//!
+//! Filters narrow down the days a trigger fires on:
+//!
+//! - `on weekdays`, `on weekends`, `on mon,wed,fri`, `on fri-sun`
+//! - `on workdays`, `on non-workdays` - according to the calendar with holidays and days off
+//! - `from 06-01 to 08-31` every year, or `from 2026-12-20 to 2027-01-06` once
+//!
+//! E.g. `09:00 on weekends`, `sunset + 30m from 05-01 to 09-30`.
+//!
+//! Times are local to the configured time zone. `dst` sets what happens to times skipped when
+//! clocks move forward (`skip` or `shift_forward`) and repeated when they move back (`once` or
+//! `both`), e.g. `every 30m dst skip,both`. Times are shifted forward and run once by default.
+
+use chrono::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+use std::time::SystemTime;
+
+use crate::calendar;
+use crate::timezone::{self, DstPolicy};
+use crate::web::Twilight;
+
+/// Number of days searched for the next occurrence before giving up
+const MAX_DAYS: u32 = 400;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SolarEvent {
+    CivilDawn,
+    Sunrise,
+    Noon,
+    Sunset,
+    CivilDusk,
+}
+
+impl SolarEvent {
+    /// Name of the event in sunrise-sunset.org results
+    pub fn api_name(&self) -> &'static str {
+        match self {
+            SolarEvent::CivilDawn => "civil_twilight_begin",
+            SolarEvent::Sunrise => "sunrise",
+            SolarEvent::Noon => "solar_noon",
+            SolarEvent::Sunset => "sunset",
+            SolarEvent::CivilDusk => "civil_twilight_end",
+        }
+    }
+}
+
+impl FromStr for SolarEvent {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "civil_dawn" => Ok(SolarEvent::CivilDawn),
+            "sunrise" => Ok(SolarEvent::Sunrise),
+            "noon" => Ok(SolarEvent::Noon),
+            "sunset" => Ok(SolarEvent::Sunset),
+            "civil_dusk" => Ok(SolarEvent::CivilDusk),
+            _ => Err(format!("Unknown solar event {}", s)),
+        }
+    }
+}
+
+/// Values allowed in a single field of a cron expression
+#[derive(Clone, Debug)]
+struct CronField {
+    values: Vec<u32>,
+    any: bool,
+}
+
+impl CronField {
+    fn parse(field: &str, min: u32, max: u32) -> Result<Self, String> {
+        let mut values = Vec::new();
+        for part in field.split(',') {
+            let (range, step) = match part.split_once('/') {
+                Some((range, step)) => (range, step.parse::<u32>().map_err(|e| format!("Invalid step {}: {}", step, e))?),
+                None => (part, 1),
+            };
+            if step == 0 {
+                return Err(format!("Invalid step in {}", part));
+            }
+            let (first, last) = if range == "*" {
+                (min, max)
+            } else if let Some((first, last)) = range.split_once('-') {
+                (Self::value(first)?, Self::value(last)?)
+            } else {
+                let value = Self::value(range)?;
+                (value, if step > 1 { max } else { value })
+            };
+            if first < min || last > max || first > last {
+                return Err(format!("Cron field {} out of range {}-{}", part, min, max));
+            }
+            values.extend((first..=last).step_by(step as usize));
+        }
+        values.sort_unstable();
+        values.dedup();
+
+        Ok(Self {
+            values,
+            any: field == "*",
+        })
+    }
+
+    fn value(value: &str) -> Result<u32, String> {
+        value.parse().map_err(|e| format!("Invalid cron value {}: {}", value, e))
+    }
+
+    fn contains(&self, value: u32) -> bool {
+        self.values.contains(&value)
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Cron {
+    minutes: CronField,
+    hours: CronField,
+    days_of_month: CronField,
+    months: CronField,
+    days_of_week: CronField,
+}
+
+impl Cron {
+    fn parse(fields: &[&str]) -> Result<Self, String> {
+        if fields.len() != 5 {
+            return Err(format!("Cron expression needs 5 fields, got {}", fields.len()));
+        }
+        Ok(Self {
+            minutes: CronField::parse(fields[0], 0, 59)?,
+            hours: CronField::parse(fields[1], 0, 23)?,
+            days_of_month: CronField::parse(fields[2], 1, 31)?,
+            months: CronField::parse(fields[3], 1, 12)?,
+            days_of_week: CronField::parse(fields[4], 0, 7)?,
+        })
+    }
+
+    fn matches_day(&self, day: NaiveDate) -> bool {
+        let weekday = day.weekday().num_days_from_sunday();
+        let dom = self.days_of_month.contains(day.day());
+        // Both 0 and 7 stand for Sunday
+        let dow = self.days_of_week.contains(weekday) || (weekday == 0 && self.days_of_week.contains(7));
+
+        // Like in cron, a day matches either restricted day field
+        let day_matches = match (self.days_of_month.any, self.days_of_week.any) {
+            (true, true) => true,
+            (false, true) => dom,
+            (true, false) => dow,
+            (false, false) => dom || dow,
+        };
+        day_matches && self.months.contains(day.month())
+    }
+
+    fn times(&self) -> Vec<NaiveTime> {
+        self.hours.values.iter()
+            .flat_map(|h| self.minutes.values.iter().filter_map(move |m| NaiveTime::from_hms_opt(*h, *m, 0)))
+            .collect()
+    }
+}
+
+#[derive(Clone, Debug)]
+enum When {
+    At(NaiveTime),
+    Every(chrono::Duration),
+    Solar(SolarEvent, chrono::Duration),
+    Cron(Cron),
+}
+
+#[derive(Clone, Debug)]
+enum Days {
+    Weekdays(Vec<Weekday>),
+    Workdays,
+    NonWorkdays,
+}
+
+impl Days {
+    fn contains(&self, day: NaiveDate) -> bool {
+        match self {
+            Days::Weekdays(weekdays) => weekdays.contains(&day.weekday()),
+            Days::Workdays => calendar::get().is_workday(day),
+            Days::NonWorkdays => !calendar::get().is_workday(day),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum DateBound {
+    /// Month and day, repeating every year
+    Yearly(u32, u32),
+    Date(NaiveDate),
+}
+
+impl FromStr for DateBound {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Ok(date) = NaiveDate::parse_from_str(s, "%Y-%m-%d") {
+            return Ok(DateBound::Date(date));
+        }
+        let (month, day) = s.split_once('-').ok_or(format!("Invalid date {}", s))?;
+        let month = month.parse().map_err(|_| format!("Invalid date {}", s))?;
+        let day = day.parse().map_err(|_| format!("Invalid date {}", s))?;
+        // Any leap year validates the day of month
+        NaiveDate::from_ymd_opt(2000, month, day).ok_or(format!("Invalid date {}", s))?;
+        Ok(DateBound::Yearly(month, day))
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+struct DateRange {
+    from: DateBound,
+    to: DateBound,
+}
+
+impl DateRange {
+    fn contains(&self, day: NaiveDate) -> bool {
+        match (self.from, self.to) {
+            (DateBound::Date(from), DateBound::Date(to)) => from <= day && day <= to,
+            (DateBound::Yearly(fm, fd), DateBound::Yearly(tm, td)) => {
+                let day = (day.month(), day.day());
+                if (fm, fd) <= (tm, td) {
+                    (fm, fd) <= day && day <= (tm, td)
+                } else {
+                    // Range over the turn of the year, e.g. 11-01 to 03-31
+                    (fm, fd) <= day || day <= (tm, td)
+                }
+            },
+            _ => false,
+        }
+    }
+}
+
+/// Rule resolving to the next time an action fires. Deserialized from its text form
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Trigger {
+    spec: String,
+    when: When,
+    days: Option<Days>,
+    dates: Option<DateRange>,
+    dst: DstPolicy,
+}
+
+impl Trigger {
+    /// Every day at `time`
+    pub fn at(time: NaiveTime) -> Self {
+        Self {
+            spec: time.format("%H:%M").to_string(),
+            when: When::At(time),
+            days: None,
+            dates: None,
+            dst: DstPolicy::default(),
+        }
+    }
+
+    fn parse_when(tokens: &[&str]) -> Result<When, String> {
+        match tokens {
+            [] => Err("Missing time".to_string()),
+            ["every", interval] => {
+                let interval = parse_duration(interval)?;
+                if interval <= chrono::Duration::zero() {
+                    return Err("Interval must be positive".to_string());
+                }
+                Ok(When::Every(interval))
+            },
+            ["cron", fields @ ..] => Ok(When::Cron(Cron::parse(fields)?)),
+            [time] if time.contains(':') => {
+                let time = NaiveTime::parse_from_str(time, "%H:%M")
+                    .or_else(|_| NaiveTime::parse_from_str(time, "%H:%M:%S"))
+                    .map_err(|e| format!("Invalid time {}: {}", time, e))?;
+                Ok(When::At(time))
+            },
+            _ => {
+                // "civil_dusk - 15m", "civil_dusk -15m" and "civil_dusk-15m" are all accepted
+                let joined = tokens.concat();
+                let (event, offset) = match joined.find(['+', '-']) {
+                    Some(idx) => {
+                        let offset = parse_duration(&joined[idx + 1..])?;
+                        (&joined[..idx], if joined[idx..].starts_with('-') { -offset } else { offset })
+                    },
+                    None => (joined.as_str(), chrono::Duration::zero()),
+                };
+                Ok(When::Solar(event.parse()?, offset))
+            },
+        }
+    }
+
+    fn parse_days(spec: &str) -> Result<Days, String> {
+        match spec {
+            "weekdays" => return Ok(Days::Weekdays(vec![Weekday::Mon, Weekday::Tue, Weekday::Wed, Weekday::Thu, Weekday::Fri])),
+            "weekends" => return Ok(Days::Weekdays(vec![Weekday::Sat, Weekday::Sun])),
+            "workdays" => return Ok(Days::Workdays),
+            "non-workdays" => return Ok(Days::NonWorkdays),
+            _ => (),
+        }
+
+        let parse = |day: &str| day.parse::<Weekday>().map_err(|_| format!("Invalid day of week {}", day));
+        let mut weekdays = Vec::new();
+        for part in spec.split(',') {
+            match part.split_once('-') {
+                Some((first, last)) => {
+                    let (mut day, last) = (parse(first)?, parse(last)?);
+                    weekdays.push(day);
+                    while day != last {
+                        day = day.succ();
+                        weekdays.push(day);
+                    }
+                },
+                None => weekdays.push(parse(part)?),
+            }
+        }
+        Ok(Days::Weekdays(weekdays))
+    }
+
+    fn matches_day(&self, day: NaiveDate) -> bool {
+        if let When::Cron(cron) = &self.when {
+            if !cron.matches_day(day) {
+                return false;
+            }
+        }
+        if let Some(days) = &self.days {
+            if !days.contains(day) {
+                return false;
+            }
+        }
+        self.dates.is_none_or(|dates| dates.contains(day))
+    }
+
+    /// Times at which the trigger fires on `day`
+    async fn times(&self, day: NaiveDate, twilight: &Twilight) -> Result<Vec<SystemTime>, String> {
+        let local_times = match &self.when {
+            When::At(time) => vec![day.and_time(*time)],
+            When::Every(interval) => {
+                let midnight = day.and_time(NaiveTime::MIN);
+                let mut times = Vec::new();
+                let mut time = midnight;
+                while time.date() == day {
+                    times.push(time);
+                    time += *interval;
+                }
+                times
+            },
+            When::Solar(event, offset) => {
+                // Solar events are instants, not affected by clock changes
+                let time = twilight.get_event(day, *event).await?;
+                let time = DateTime::<Utc>::from(time) + *offset;
+                return Ok(vec![time.into()]);
+            },
+            When::Cron(cron) => cron.times().into_iter().map(|t| day.and_time(t)).collect(),
+        };
+
+        Ok(local_times.into_iter()
+            .flat_map(|time| timezone::resolve(time, self.dst))
+            .collect())
+    }
+
+    /// First time the trigger fires after `after`
+    pub async fn next_after(&self, after: SystemTime, twilight: &Twilight) -> Result<SystemTime, String> {
+        let mut day = timezone::from_system(after).date_naive();
+        // Solar offsets may move an occurrence into the previous day
+        if matches!(self.when, When::Solar(..)) {
+            day = day.pred_opt().unwrap_or(day);
+        }
+
+        for _ in 0..MAX_DAYS {
+            if self.matches_day(day) {
+                // Times repeated when clocks move back may come out of order
+                let next = self.times(day, twilight).await?.into_iter()
+                    .filter(|time| *time > after)
+                    .min();
+                if let Some(next) = next {
+                    return Ok(next);
+                }
+            }
+            day = day.succ_opt().ok_or("Date out of range".to_string())?;
+        }
+
+        Err(format!("Trigger {} does not fire within {} days", self.spec, MAX_DAYS))
+    }
+}
+
+impl FromStr for Trigger {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let tokens: Vec<&str> = s.split_whitespace().collect();
+        let filters = tokens.iter().position(|t| ["on", "from", "dst"].contains(t)).unwrap_or(tokens.len());
+        let when = Self::parse_when(&tokens[..filters])?;
+
+        let mut days = None;
+        let mut dates = None;
+        let mut dst = DstPolicy::default();
+        let mut rest = &tokens[filters..];
+        while !rest.is_empty() {
+            match rest {
+                ["on", spec, tail @ ..] => {
+                    days = Some(Self::parse_days(spec)?);
+                    rest = tail;
+                },
+                ["from", from, "to", to, tail @ ..] => {
+                    let (from, to) = (from.parse()?, to.parse()?);
+                    if matches!((from, to), (DateBound::Date(_), DateBound::Yearly(..)) | (DateBound::Yearly(..), DateBound::Date(_))) {
+                        return Err(format!("Dates {:?} and {:?} must both include the year or both omit it", from, to));
+                    }
+                    dates = Some(DateRange { from, to });
+                    rest = tail;
+                },
+                ["dst", policy, tail @ ..] => {
+                    dst = policy.parse()?;
+                    rest = tail;
+                },
+                _ => return Err(format!("Cannot parse {} in trigger {}", rest.join(" "), s)),
+            }
+        }
+
+        Ok(Self {
+            spec: tokens.join(" "),
+            when,
+            days,
+            dates,
+            dst,
+        })
+    }
+}
+
+impl TryFrom<String> for Trigger {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<Trigger> for String {
+    fn from(trigger: Trigger) -> Self {
+        trigger.spec
+    }
+}
+
+impl fmt::Display for Trigger {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.spec)
+    }
+}
+
+/// Durations like "15m", "2h" or "1h30m"
+fn parse_duration(s: &str) -> Result<chrono::Duration, String> {
+    let mut total = chrono::Duration::zero();
+    let mut number = String::new();
+    for c in s.chars() {
+        if c.is_ascii_digit() {
+            number.push(c);
+            continue;
+        }
+        let value: i64 = number.parse().map_err(|_| format!("Invalid duration {}", s))?;
+        total += match c {
+            'h' => chrono::Duration::hours(value),
+            'm' => chrono::Duration::minutes(value),
+            's' => chrono::Duration::seconds(value),
+            _ => return Err(format!("Invalid duration unit {} in {}", c, s)),
+        };
+        number.clear();
+    }
+    if !number.is_empty() || s.is_empty() {
+        return Err(format!("Duration {} needs a unit: h, m or s", s));
+    }
+    Ok(total)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use super::*;
+    use crate::notify::{Notifier, NotifyConfig};
+
+    fn twilight() -> Twilight {
+        timezone::init(Some(chrono_tz::Europe::Warsaw));
+        Twilight::new(Arc::new(Notifier::new(NotifyConfig::default(), None).unwrap()))
+    }
+
+    fn utc(time: &str) -> SystemTime {
+        DateTime::parse_from_rfc3339(time).unwrap().into()
+    }
+
+    /// Successive times the trigger fires after `after`, up to `until`
+    async fn fires(spec: &str, after: &str, until: &str) -> Vec<SystemTime> {
+        let twilight = twilight();
+        let trigger: Trigger = spec.parse().unwrap();
+        let (mut after, until) = (utc(after), utc(until));
+        let mut times = Vec::new();
+        loop {
+            after = trigger.next_after(after, &twilight).await.unwrap();
+            if after > until {
+                return times;
+            }
+            times.push(after);
+        }
+    }
+
+    fn every(first: &str, minutes: u64, cnt: u32) -> Vec<SystemTime> {
+        (0..cnt).map(|i| utc(first) + Duration::from_secs(minutes * 60) * i).collect()
+    }
+
+    async fn next(spec: &str, after: &str) -> Result<SystemTime, String> {
+        let trigger: Trigger = spec.parse().unwrap();
+        trigger.next_after(utc(after), &twilight()).await
+    }
+
+    fn day(date: &str) -> NaiveDate {
+        date.parse().unwrap()
+    }
+
+    #[test]
+    fn durations() {
+        assert_eq!(parse_duration("15m"), Ok(chrono::Duration::minutes(15)));
+        assert_eq!(parse_duration("2h"), Ok(chrono::Duration::hours(2)));
+        assert_eq!(parse_duration("1h30m"), Ok(chrono::Duration::minutes(90)));
+        assert_eq!(parse_duration("45s"), Ok(chrono::Duration::seconds(45)));
+        for invalid in ["", "15", "m", "15x", "1.5h", "-15m"] {
+            assert!(parse_duration(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn cron_fields() {
+        let values = |field, min, max| CronField::parse(field, min, max).map(|field| field.values);
+        assert_eq!(values("*/15", 0, 59), Ok(vec![0, 15, 30, 45]));
+        assert_eq!(values("5/20", 0, 59), Ok(vec![5, 25, 45]));
+        assert_eq!(values("1-5", 0, 7), Ok(vec![1, 2, 3, 4, 5]));
+        assert_eq!(values("30,0,10-12", 0, 59), Ok(vec![0, 10, 11, 12, 30]));
+        for invalid in ["60", "5-1", "*/0", "a", "1-"] {
+            assert!(values(invalid, 0, 59).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn solar_offsets() {
+        for spec in ["civil_dusk - 15m", "civil_dusk -15m", "civil_dusk-15m"] {
+            let trigger: Trigger = spec.parse().unwrap();
+            assert!(matches!(trigger.when, When::Solar(SolarEvent::CivilDusk, offset) if offset == chrono::Duration::minutes(-15)), "{}", spec);
+        }
+        let trigger: Trigger = "sunrise + 1h".parse().unwrap();
+        assert!(matches!(trigger.when, When::Solar(SolarEvent::Sunrise, offset) if offset == chrono::Duration::hours(1)));
+        let trigger: Trigger = "noon".parse().unwrap();
+        assert!(matches!(trigger.when, When::Solar(SolarEvent::Noon, offset) if offset.is_zero()));
+    }
+
+    #[test]
+    fn date_ranges() {
+        let range = |from: &str, to: &str| DateRange { from: from.parse().unwrap(), to: to.parse().unwrap() };
+
+        let summer = range("06-01", "08-31");
+        assert!(summer.contains(day("2026-06-01")) && summer.contains(day("2027-08-31")));
+        assert!(!summer.contains(day("2026-09-01")));
+
+        // Over the new year
+        let winter = range("12-24", "01-06");
+        assert!(winter.contains(day("2026-12-31")) && winter.contains(day("2027-01-01")) && winter.contains(day("2027-01-06")));
+        assert!(!winter.contains(day("2026-12-23")) && !winter.contains(day("2027-01-07")));
+
+        let once = range("2026-12-20", "2027-01-06");
+        assert!(once.contains(day("2027-01-01")));
+        assert!(!once.contains(day("2028-01-01")));
+    }
+
+    #[test]
+    fn parse_errors() {
+        let invalid = [
+            "", "on weekdays", "25:00", "7", "moonrise", "sunset - 30", "every", "every 0m",
+            "cron 0 7 * *", "cron 61 * * * *", "07:00 on funday", "07:00 on", "07:00 from 02-30 to 03-01",
+            "07:00 from 06-01", "07:00 from 2026-06-01 to 08-31", "07:00 dst never", "07:00 at noon",
+        ];
+        for spec in invalid {
+            assert!(spec.parse::<Trigger>().is_err(), "{}", spec);
+        }
+    }
+
+    #[test]
+    fn text_form() {
+        let trigger: Trigger = serde_json::from_str(r#""09:00  on   weekends""#).unwrap();
+        assert_eq!(serde_json::to_string(&trigger).unwrap(), r#""09:00 on weekends""#);
+        assert_eq!(Trigger::at(NaiveTime::from_hms_opt(7, 5, 0).unwrap()).to_string(), "07:05");
+    }
+
+    #[tokio::test]
+    async fn at_and_every() {
+        assert_eq!(next("07:30", "2026-10-19T04:00:00Z").await, Ok(utc("2026-10-19T05:30:00Z")));
+        assert_eq!(next("07:30", "2026-10-19T05:30:00Z").await, Ok(utc("2026-10-20T05:30:00Z")));
+        assert_eq!(next("every 15m", "2026-10-19T10:07:00Z").await, Ok(utc("2026-10-19T10:15:00Z")));
+        // Intervals count from local midnight
+        assert_eq!(next("every 2h", "2026-10-19T23:30:00Z").await, Ok(utc("2026-10-20T00:00:00Z")));
+    }
+
+    #[tokio::test]
+    async fn cron() {
+        // 07:00 CET on Monday after the clock change
+        assert_eq!(next("cron 0 7 * * 1-5", "2026-10-24T12:00:00Z").await, Ok(utc("2026-10-26T06:00:00Z")));
+        assert_eq!(next("cron 30 */6 * * *", "2026-10-19T05:00:00Z").await, Ok(utc("2026-10-19T10:30:00Z")));
+        // Restricted day of month and day of week both match, so the 13th or Fridays
+        assert_eq!(next("cron 0 9 13 * 5", "2026-10-19T12:00:00Z").await, Ok(utc("2026-10-23T07:00:00Z")));
+        assert_eq!(next("cron 0 9 13 * 5", "2026-11-12T12:00:00Z").await, Ok(utc("2026-11-13T08:00:00Z")));
+        // Sunday as 7
+        assert_eq!(next("cron 0 9 * * 7", "2026-10-19T12:00:00Z").await, Ok(utc("2026-10-25T08:00:00Z")));
+    }
+
+    #[tokio::test]
+    async fn day_filters() {
+        // 2026-10-19 is a Monday
+        assert_eq!(next("09:00 on weekends", "2026-10-19T12:00:00Z").await, Ok(utc("2026-10-24T07:00:00Z")));
+        assert_eq!(next("09:00 on weekdays", "2026-10-23T12:00:00Z").await, Ok(utc("2026-10-26T08:00:00Z")));
+        assert_eq!(next("09:00 on mon,wed,fri", "2026-10-19T12:00:00Z").await, Ok(utc("2026-10-21T07:00:00Z")));
+        assert_eq!(next("09:00 on fri-sun", "2026-10-19T12:00:00Z").await, Ok(utc("2026-10-23T07:00:00Z")));
+        // Ranges of days may wrap over the end of the week
+        assert_eq!(next("09:00 on sat-mon", "2026-10-25T12:00:00Z").await, Ok(utc("2026-10-26T08:00:00Z")));
+
+        // Independence Day on Wednesday 2026-11-11
+        assert_eq!(next("07:00 on workdays", "2026-11-10T12:00:00Z").await, Ok(utc("2026-11-12T06:00:00Z")));
+        assert_eq!(next("10:00 on non-workdays", "2026-11-10T12:00:00Z").await, Ok(utc("2026-11-11T09:00:00Z")));
+    }
+
+    #[tokio::test]
+    async fn date_filters() {
+        assert_eq!(next("12:00 from 06-01 to 08-31", "2026-10-19T12:00:00Z").await, Ok(utc("2027-06-01T10:00:00Z")));
+        assert_eq!(next("12:00 from 12-24 to 01-06", "2026-12-31T12:00:00Z").await, Ok(utc("2027-01-01T11:00:00Z")));
+        assert_eq!(next("12:00 from 12-24 to 01-06", "2027-01-06T12:00:00Z").await, Ok(utc("2027-12-24T11:00:00Z")));
+        assert!(next("12:00 from 2026-12-20 to 2027-01-06", "2027-01-06T12:00:00Z").await.is_err());
+        assert_eq!(next("12:00 on weekends from 12-24 to 01-06", "2026-12-20T12:00:00Z").await, Ok(utc("2026-12-26T11:00:00Z")));
+    }
+
+    #[tokio::test]
+    async fn solar() {
+        let twilight = twilight();
+        twilight.set_events(day("2026-10-18"), &[(SolarEvent::Sunset, utc("2026-10-18T15:00:00Z"))]);
+        twilight.set_events(day("2026-10-19"), &[(SolarEvent::Sunset, utc("2026-10-19T14:58:00Z"))]);
+        twilight.set_events(day("2026-10-20"), &[(SolarEvent::Sunset, utc("2026-10-20T14:56:00Z"))]);
+
+        let fires_at = |spec: &str, after: &str| {
+            let trigger: Trigger = spec.parse().unwrap();
+            let after = utc(after);
+            let twilight = &twilight;
+            async move { trigger.next_after(after, twilight).await }
+        };
+        assert_eq!(fires_at("sunset - 30m", "2026-10-19T12:00:00Z").await, Ok(utc("2026-10-19T14:28:00Z")));
+        assert_eq!(fires_at("sunset + 30m", "2026-10-19T15:20:00Z").await, Ok(utc("2026-10-19T15:28:00Z")));
+        assert_eq!(fires_at("sunset", "2026-10-19T15:00:00Z").await, Ok(utc("2026-10-20T14:56:00Z")));
+        // Missing in the fetched data
+        assert!(fires_at("sunrise", "2026-10-19T12:00:00Z").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn every_across_spring_forward() {
+        // 00:00 CET to 04:00 CEST. Times in the gap fall onto existing ones, so nothing fires twice
+        for spec in ["every 30m", "every 30m dst skip"] {
+            let times = fires(spec, "2026-03-28T22:59:00Z", "2026-03-29T02:00:00Z").await;
+            assert_eq!(times, every("2026-03-28T23:00:00Z", 30, 7));
+        }
+    }
+
+    #[tokio::test]
+    async fn every_across_fall_back() {
+        // 00:00 CEST to 03:30 CET. The repeated hour from 02:00 is skipped by default
+        let times = fires("every 30m", "2026-10-24T21:59:00Z", "2026-10-25T02:30:00Z").await;
+        let mut expected = every("2026-10-24T22:00:00Z", 30, 6);
+        expected.extend(every("2026-10-25T02:00:00Z", 30, 2));
+        assert_eq!(times, expected);
+
+        let times = fires("every 30m dst both", "2026-10-24T21:59:00Z", "2026-10-25T02:30:00Z").await;
+        assert_eq!(times, every("2026-10-24T22:00:00Z", 30, 10));
+    }
+
+    #[tokio::test]
+    async fn at_in_gap_and_fold() {
+        // 03:30 CEST on the day of the change, then 02:30 CEST
+        let times = fires("02:30", "2026-03-28T12:00:00Z", "2026-03-30T12:00:00Z").await;
+        assert_eq!(times, vec![utc("2026-03-29T01:30:00Z"), utc("2026-03-30T00:30:00Z")]);
+
+        let times = fires("02:30 dst skip", "2026-03-28T12:00:00Z", "2026-03-30T12:00:00Z").await;
+        assert_eq!(times, vec![utc("2026-03-30T00:30:00Z")]);
+
+        let times = fires("02:30 dst both", "2026-10-24T12:00:00Z", "2026-10-25T12:00:00Z").await;
+        assert_eq!(times, vec![utc("2026-10-25T00:30:00Z"), utc("2026-10-25T01:30:00Z")]);
+    }
+}
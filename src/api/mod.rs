@@ -0,0 +1,125 @@
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, StatusCode};
+use serde::{Deserialize, Serialize};
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use crate::coap::{DeviceKind, DeviceReport};
+use crate::metrics;
+use crate::state::{HcState, Health, HvacState, Overrides, Presence, Schedules, Signal};
+
+/// Body of POST /presence, e.g. {"signal": "arriving", "source": "phone"}
+#[derive(Deserialize)]
+struct PresenceReport {
+    signal: Signal,
+    #[serde(default = "PresenceReport::default_source")]
+    source: String,
+}
+
+impl PresenceReport {
+    fn default_source() -> String {
+        "api".to_string()
+    }
+}
+
+#[derive(Serialize)]
+struct Status {
+    hvac: Option<HcState>,
+    devices: Vec<DeviceReport>,
+}
+
+pub struct Server {
+    hvac_state: Arc<HvacState>,
+    overrides: Arc<Overrides>,
+    health: Arc<Health>,
+    schedules: Arc<Schedules>,
+    presence: Arc<Presence>,
+    devices: Vec<(DeviceKind, String)>,
+}
+
+impl Server {
+    pub fn new(hvac_state: Arc<HvacState>,
+               overrides: Arc<Overrides>,
+               health: Arc<Health>,
+               schedules: Arc<Schedules>,
+               presence: Arc<Presence>,
+               devices: Vec<(DeviceKind, String)>,
+              ) -> Self {
+        Self {
+            hvac_state,
+            overrides,
+            health,
+            schedules,
+            presence,
+            devices,
+        }
+    }
+
+    pub async fn serve(self: Arc<Self>, addr: SocketAddr) -> Result<(), String> {
+        let make_service = make_service_fn(move |_| {
+            let server = self.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |req| {
+                    let server = server.clone();
+                    async move { Ok::<_, Infallible>(server.handle(req).await) }
+                }))
+            }
+        });
+
+        log::info!("Serving API on {}", addr);
+        hyper::Server::try_bind(&addr).map_err(|e| e.to_string())?
+            .serve(make_service).await
+            .map_err(|e| e.to_string())
+    }
+
+    async fn handle(&self, req: Request<Body>) -> Response<Body> {
+        match (req.method().clone(), req.uri().path()) {
+            (Method::GET, "/health") => Self::json(&self.health.get_all().await),
+            (Method::GET, "/metrics") => Response::builder()
+                .header("Content-Type", "text/plain; version=0.0.4")
+                .body(Body::from(metrics::render(&self.hvac_state, &self.schedules, &self.devices).await))
+                .unwrap(),
+            (Method::GET, "/hvac/decisions") => Self::json(&self.hvac_state.get_decisions().await),
+            (Method::GET, "/overrides") => Self::json(&self.overrides.get_all().await),
+            (Method::GET, "/presence") => Self::json(&self.presence.status()),
+            (Method::POST, "/presence") => self.report_presence(req).await,
+            (Method::GET, "/status") => Self::json(&Status {
+                hvac: self.hvac_state.current_state(),
+                devices: DeviceReport::read_all(&self.devices).await,
+            }),
+            _ => Self::status(StatusCode::NOT_FOUND),
+        }
+    }
+
+    async fn report_presence(&self, req: Request<Body>) -> Response<Body> {
+        let body = match hyper::body::to_bytes(req.into_body()).await {
+            Ok(body) => body,
+            Err(_) => return Self::status(StatusCode::BAD_REQUEST),
+        };
+        match serde_json::from_slice::<PresenceReport>(&body) {
+            Ok(report) => {
+                self.presence.signal(report.signal, &report.source).await;
+                Self::json(&self.presence.status())
+            },
+            Err(_) => Self::status(StatusCode::BAD_REQUEST),
+        }
+    }
+
+    fn json<T: Serialize>(value: &T) -> Response<Body> {
+        match serde_json::to_vec(value) {
+            Ok(body) => Response::builder()
+                .header("Content-Type", "application/json")
+                .body(Body::from(body))
+                .unwrap(),
+            Err(_) => Self::status(StatusCode::INTERNAL_SERVER_ERROR),
+        }
+    }
+
+    fn status(status: StatusCode) -> Response<Body> {
+        Response::builder()
+            .status(status)
+            .body(Body::empty())
+            .unwrap()
+    }
+}
@@ -0,0 +1,106 @@
+use chrono::prelude::*;
+
+/// Easter Sunday in the Gregorian calendar (anonymous Gregorian algorithm)
+pub fn easter(year: i32) -> NaiveDate {
+    let a = year % 19;
+    let b = year / 100;
+    let c = year % 100;
+    let d = b / 4;
+    let e = b % 4;
+    let f = (b + 8) / 25;
+    let g = (b - f + 1) / 3;
+    let h = (19 * a + b - d - g + 15) % 30;
+    let i = c / 4;
+    let k = c % 4;
+    let l = (32 + 2 * e + 2 * i - h - k) % 7;
+    let m = (a + 11 * h + 22 * l) / 451;
+    let month = (h + l - 7 * m + 114) / 31;
+    let day = (h + l - 7 * m + 114) % 31 + 1;
+
+    NaiveDate::from_ymd_opt(year, month as u32, day as u32).unwrap()
+}
+
+/// Name of the Polish public holiday falling on `day`
+pub fn polish(day: NaiveDate) -> Option<&'static str> {
+    let fixed = match (day.month(), day.day()) {
+        (1, 1) => Some("New Year's Day"),
+        (1, 6) => Some("Epiphany"),
+        (5, 1) => Some("Labour Day"),
+        (5, 3) => Some("Constitution Day"),
+        (8, 15) => Some("Assumption Day"),
+        (11, 1) => Some("All Saints' Day"),
+        (11, 11) => Some("Independence Day"),
+        // A public holiday since 2025
+        (12, 24) if day.year() >= 2025 => Some("Christmas Eve"),
+        (12, 25) => Some("Christmas Day"),
+        (12, 26) => Some("Second Day of Christmas"),
+        _ => None,
+    };
+    if fixed.is_some() {
+        return fixed;
+    }
+
+    match (day - easter(day.year())).num_days() {
+        0 => Some("Easter Sunday"),
+        1 => Some("Easter Monday"),
+        49 => Some("Pentecost"),
+        60 => Some("Corpus Christi"),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn day(date: &str) -> NaiveDate {
+        date.parse().unwrap()
+    }
+
+    #[test]
+    fn easter_sundays() {
+        let known = [
+            (1943, "1943-04-25"), (2019, "2019-04-21"), (2024, "2024-03-31"), (2025, "2025-04-20"),
+            (2026, "2026-04-05"), (2038, "2038-04-25"), (2285, "2285-03-22"),
+        ];
+        for (year, date) in known {
+            assert_eq!(easter(year), day(date), "{}", year);
+        }
+    }
+
+    #[test]
+    fn polish_holidays() {
+        let known = [
+            ("2025-01-01", Some("New Year's Day")),
+            ("2025-01-06", Some("Epiphany")),
+            ("2025-04-20", Some("Easter Sunday")),
+            ("2025-04-21", Some("Easter Monday")),
+            ("2025-05-01", Some("Labour Day")),
+            ("2025-05-03", Some("Constitution Day")),
+            ("2025-06-08", Some("Pentecost")),
+            ("2025-06-19", Some("Corpus Christi")),
+            ("2025-08-15", Some("Assumption Day")),
+            ("2025-11-01", Some("All Saints' Day")),
+            ("2025-11-11", Some("Independence Day")),
+            ("2025-12-25", Some("Christmas Day")),
+            ("2025-12-26", Some("Second Day of Christmas")),
+            ("2024-04-01", Some("Easter Monday")),
+            ("2024-05-30", Some("Corpus Christi")),
+            ("2026-04-06", Some("Easter Monday")),
+            ("2026-06-04", Some("Corpus Christi")),
+            ("2025-04-18", None),
+            ("2025-06-20", None),
+            ("2025-12-27", None),
+        ];
+        for (date, holiday) in known {
+            assert_eq!(polish(day(date)), holiday, "{}", date);
+        }
+    }
+
+    #[test]
+    fn christmas_eve_since_2025() {
+        assert_eq!(polish(day("2024-12-24")), None);
+        assert_eq!(polish(day("2025-12-24")), Some("Christmas Eve"));
+        assert_eq!(polish(day("2026-12-24")), Some("Christmas Eve"));
+    }
+}
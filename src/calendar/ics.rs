@@ -0,0 +1,163 @@
+//! Minimal iCalendar reader taking the days covered by events
+
+use chrono::prelude::*;
+use chrono_tz::Tz;
+use std::path::Path;
+
+use crate::timezone;
+
+/// First and last day of every event in the file. Events with start and end times, e.g.
+/// meetings, are only taken if `timed` is set, otherwise only all-day events are
+pub fn load(path: &Path, timed: bool) -> Result<Vec<(NaiveDate, NaiveDate)>, String> {
+    let content = std::fs::read_to_string(path)
+        .map_err(|e| format!("Cannot read calendar {}: {}", path.display(), e))?;
+    parse(&content, timed).map_err(|e| format!("Cannot parse calendar {}: {}", path.display(), e))
+}
+
+fn parse(content: &str, timed: bool) -> Result<Vec<(NaiveDate, NaiveDate)>, String> {
+    // Long lines are folded by starting the continuation with a space or a tab
+    let unfolded = content.replace("\r\n", "\n").replace("\n ", "").replace("\n\t", "");
+
+    let mut events = Vec::new();
+    let mut event: Option<Event> = None;
+    for line in unfolded.lines() {
+        let Some((name, value)) = line.split_once(':') else { continue };
+        // Parameters follow the property name, e.g. DTSTART;VALUE=DATE:20261224
+        let (name, params) = name.split_once(';').unwrap_or((name, ""));
+
+        match (name, value) {
+            ("BEGIN", "VEVENT") => event = Some(Event::default()),
+            ("END", "VEVENT") => {
+                if let Some(event) = event.take() {
+                    if let Some(days) = event.days(timed)? {
+                        events.push(days);
+                    }
+                }
+            },
+            _ => if let Some(event) = event.as_mut() {
+                match name {
+                    "DTSTART" => event.start = Some(Time::parse(value, params)?),
+                    "DTEND" => event.end = Some(Time::parse(value, params)?),
+                    "STATUS" => event.cancelled = value == "CANCELLED",
+                    "RRULE" => event.recurring = true,
+                    "SUMMARY" => event.summary = value.to_string(),
+                    _ => (),
+                }
+            },
+        }
+    }
+
+    Ok(events)
+}
+
+#[derive(Clone, Copy)]
+enum Time {
+    Date(NaiveDate),
+    /// Local time in the configured time zone
+    DateTime(NaiveDateTime),
+}
+
+impl Time {
+    fn parse(value: &str, params: &str) -> Result<Self, String> {
+        if (params.contains("VALUE=DATE") && !params.contains("VALUE=DATE-TIME")) || !value.contains('T') {
+            return NaiveDate::parse_from_str(value, "%Y%m%d").map(Time::Date)
+                .map_err(|e| format!("Invalid date {}: {}", value, e));
+        }
+
+        // UTC times end with Z, others are local to TZID or floating if it is missing
+        let time = NaiveDateTime::parse_from_str(value.trim_end_matches('Z'), "%Y%m%dT%H%M%S")
+            .map_err(|e| format!("Invalid time {}: {}", value, e))?;
+        let tzid = params.split(';').find_map(|p| p.strip_prefix("TZID=")).map(|tzid| tzid.trim_matches('"'));
+        let instant = if value.ends_with('Z') {
+            Some(time.and_utc())
+        } else if let Some(tzid) = tzid {
+            match tzid.parse::<Tz>() {
+                Ok(tz) => tz.from_local_datetime(&time).earliest().map(|t| t.to_utc()),
+                Err(_) => {
+                    log::warn!("Unknown time zone {}, using {} as local time", tzid, value);
+                    None
+                },
+            }
+        } else {
+            None
+        };
+
+        Ok(Time::DateTime(match instant {
+            Some(instant) => timezone::from_system(instant.into()).naive_local(),
+            None => time,
+        }))
+    }
+}
+
+#[derive(Default)]
+struct Event {
+    start: Option<Time>,
+    end: Option<Time>,
+    cancelled: bool,
+    recurring: bool,
+    summary: String,
+}
+
+impl Event {
+    fn days(&self, timed: bool) -> Result<Option<(NaiveDate, NaiveDate)>, String> {
+        if self.cancelled || (!timed && matches!(self.start, Some(Time::DateTime(_)))) {
+            return Ok(None);
+        }
+        if self.recurring {
+            log::warn!("Recurring events are not supported, using the first occurrence of {}", self.summary);
+        }
+
+        let first = match self.start {
+            Some(Time::Date(date)) => date,
+            Some(Time::DateTime(time)) => time.date(),
+            None => return Err(format!("Event {} without DTSTART", self.summary)),
+        };
+        // The end is exclusive: all-day events end on the next day, others may end at midnight
+        let last = match self.end {
+            Some(Time::Date(date)) => date.pred_opt().unwrap_or(date),
+            Some(Time::DateTime(time)) if time.time() == NaiveTime::MIN => time.date().pred_opt().unwrap_or(time.date()),
+            Some(Time::DateTime(time)) => time.date(),
+            None => first,
+        };
+
+        Ok(Some((first, last.max(first))))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn day(day: &str) -> NaiveDate {
+        NaiveDate::parse_from_str(day, "%Y-%m-%d").unwrap()
+    }
+
+    fn event(start: &str, end: &str) -> String {
+        format!("BEGIN:VCALENDAR\r\nBEGIN:VEVENT\r\nSUMMARY:Test\r\n{}\r\n{}\r\nEND:VEVENT\r\nEND:VCALENDAR\r\n", start, end)
+    }
+
+    #[test]
+    fn all_day() {
+        timezone::init(Some(chrono_tz::Europe::Warsaw));
+        let content = event("DTSTART;VALUE=DATE:20261224", "DTEND;VALUE=DATE:20261227");
+        assert_eq!(parse(&content, false), Ok(vec![(day("2026-12-24"), day("2026-12-26"))]));
+    }
+
+    #[test]
+    fn timed_only_if_enabled() {
+        timezone::init(Some(chrono_tz::Europe::Warsaw));
+        let content = event("DTSTART;TZID=Europe/Warsaw:20261221T100000", "DTEND;TZID=Europe/Warsaw:20261221T110000");
+        assert_eq!(parse(&content, false), Ok(vec![]));
+        assert_eq!(parse(&content, true), Ok(vec![(day("2026-12-21"), day("2026-12-21"))]));
+    }
+
+    #[test]
+    fn converted_to_configured_zone() {
+        timezone::init(Some(chrono_tz::Europe::Warsaw));
+        // 00:00 to 02:00 on Christmas Eve in Warsaw
+        let content = event("DTSTART:20261223T230000Z", "DTEND:20261224T010000Z");
+        assert_eq!(parse(&content, true), Ok(vec![(day("2026-12-24"), day("2026-12-24"))]));
+        let content = event("DTSTART;TZID=America/New_York:20261223T180000", "DTEND;TZID=America/New_York:20261223T200000");
+        assert_eq!(parse(&content, true), Ok(vec![(day("2026-12-24"), day("2026-12-24"))]));
+    }
+}
@@ -0,0 +1,174 @@
+//! Working days and days off: public holidays, configured days off and imported calendars
+
+mod holidays;
+mod ics;
+
+use chrono::prelude::*;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+
+static CALENDAR: OnceLock<Calendar> = OnceLock::new();
+
+/// Inclusive range of days, e.g. "2026-07-01..2026-07-14", or a single day "2026-08-14"
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Days {
+    pub first: NaiveDate,
+    pub last: NaiveDate,
+}
+
+impl TryFrom<String> for Days {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        let parse = |day: &str| NaiveDate::parse_from_str(day, "%Y-%m-%d").map_err(|e| format!("Invalid day {}: {}", day, e));
+        let (first, last) = match value.split_once("..") {
+            Some((first, last)) => (parse(first)?, parse(last)?),
+            None => (parse(&value)?, parse(&value)?),
+        };
+        if first > last {
+            return Err(format!("Days {} end before they start", value));
+        }
+        Ok(Self { first, last })
+    }
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(default)]
+pub struct CalendarConfig {
+    /// Polish public holidays are days off
+    pub public_holidays: bool,
+    pub days_off: Vec<Days>,
+    /// iCalendar files whose all-day events are days off, e.g. an exported vacation calendar
+    pub ics: Vec<PathBuf>,
+    /// Events with start and end times in the files also make their days off
+    pub ics_timed_events: bool,
+}
+
+impl Default for CalendarConfig {
+    fn default() -> Self {
+        Self {
+            public_holidays: true,
+            days_off: Vec::new(),
+            ics: Vec::new(),
+            ics_timed_events: false,
+        }
+    }
+}
+
+pub struct Calendar {
+    public_holidays: bool,
+    /// Reason of each day off
+    days_off: BTreeMap<NaiveDate, String>,
+}
+
+impl Calendar {
+    pub fn new(config: &CalendarConfig) -> Result<Self, String> {
+        let mut calendar = Self {
+            public_holidays: config.public_holidays,
+            days_off: BTreeMap::new(),
+        };
+
+        for days in &config.days_off {
+            calendar.add(days.first, days.last, "Day off");
+        }
+        for path in &config.ics {
+            let events = ics::load(path, config.ics_timed_events)?;
+            log::info!("Loaded {} events from {}", events.len(), path.display());
+            for (first, last) in events {
+                calendar.add(first, last, &path.display().to_string());
+            }
+        }
+
+        Ok(calendar)
+    }
+
+    fn add(&mut self, first: NaiveDate, last: NaiveDate, reason: &str) {
+        for day in first.iter_days().take_while(|d| *d <= last) {
+            self.days_off.entry(day).or_insert_with(|| reason.to_string());
+        }
+    }
+
+    /// Why `day` is off, unless it is a working day
+    pub fn day_off(&self, day: NaiveDate) -> Option<String> {
+        if let Some(reason) = self.days_off.get(&day) {
+            return Some(reason.clone());
+        }
+        if self.public_holidays {
+            if let Some(holiday) = holidays::polish(day) {
+                return Some(holiday.to_string());
+            }
+        }
+        match day.weekday() {
+            Weekday::Sat | Weekday::Sun => Some("Weekend".to_string()),
+            _ => None,
+        }
+    }
+
+    pub fn is_workday(&self, day: NaiveDate) -> bool {
+        self.day_off(day).is_none()
+    }
+}
+
+/// Sets the calendar used by schedules. Only public holidays are known if not set
+pub fn init(config: &CalendarConfig) -> Result<(), String> {
+    let calendar = Calendar::new(config)?;
+    CALENDAR.set(calendar).map_err(|_| "Calendar already set".to_string())
+}
+
+pub fn get() -> &'static Calendar {
+    CALENDAR.get_or_init(|| Calendar::new(&CalendarConfig::default()).unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn calendar(config: &str) -> Calendar {
+        Calendar::new(&serde_json::from_str(config).unwrap()).unwrap()
+    }
+
+    fn day(date: &str) -> NaiveDate {
+        date.parse().unwrap()
+    }
+
+    #[test]
+    fn workdays() {
+        let calendar = calendar(r#"{"days_off": ["2026-07-01..2026-07-03", "2026-08-14"]}"#);
+        let known = [
+            // Monday, then Saturday and Sunday
+            ("2026-06-29", true), ("2026-06-27", false), ("2026-06-28", false),
+            // Configured days off on Wednesday to Friday, and Friday before Assumption Day
+            ("2026-06-30", true), ("2026-07-01", false), ("2026-07-03", false), ("2026-07-06", true),
+            ("2026-08-14", false), ("2026-08-15", false),
+            // Independence Day on Wednesday, Christmas Eve on Thursday
+            ("2026-11-11", false), ("2026-11-12", true), ("2026-12-24", false), ("2024-12-24", true),
+        ];
+        for (date, workday) in known {
+            assert_eq!(calendar.is_workday(day(date)), workday, "{}", date);
+        }
+        assert_eq!(calendar.day_off(day("2026-07-02")), Some("Day off".to_string()));
+        assert_eq!(calendar.day_off(day("2026-11-11")), Some("Independence Day".to_string()));
+    }
+
+    #[test]
+    fn without_public_holidays() {
+        let calendar = calendar(r#"{"public_holidays": false}"#);
+        assert!(calendar.is_workday(day("2026-11-11")));
+        assert!(!calendar.is_workday(day("2026-11-14")));
+    }
+
+    #[test]
+    fn days() {
+        let days = Days::try_from("2026-07-01..2026-07-14".to_string()).unwrap();
+        assert_eq!((days.first, days.last), (day("2026-07-01"), day("2026-07-14")));
+        let days = Days::try_from("2026-08-14".to_string()).unwrap();
+        assert_eq!((days.first, days.last), (day("2026-08-14"), day("2026-08-14")));
+
+        for invalid in ["2026-07-14..2026-07-01", "2026-02-30", "07-01..07-14", ""] {
+            assert!(Days::try_from(invalid.to_string()).is_err(), "{}", invalid);
+        }
+    }
+}
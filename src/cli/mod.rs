@@ -0,0 +1,185 @@
+use rust_decimal::prelude::*;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use crate::actuators::{self, cron_processor::{CronProcessor, Planned}};
+use crate::coap::{self, DeviceKind, DeviceStatus};
+use crate::config::Config;
+use crate::notify::{Notifier, NotifyConfig};
+use crate::state::{HvacState, Occupancy, Overrides, Presence, Schedules};
+use crate::timezone;
+use crate::web;
+use crate::Args;
+
+/// One-shot commands only log notifications, they do not send them anywhere
+fn notifier() -> Arc<Notifier> {
+    Arc::new(Notifier::new(NotifyConfig::default(), None).unwrap())
+}
+
+fn weather(args: &Args, notifier: Arc<Notifier>) -> web::Weather {
+    web::Weather::new(args.openweathermap_token.clone(), args.visualcrossing_token.clone(), notifier)
+}
+
+fn format_time(time: SystemTime) -> String {
+    timezone::from_system(time).format("%Y-%m-%d %H:%M:%S").to_string()
+}
+
+fn device_kind(config: &Config, rsrc: &str) -> Result<DeviceKind, String> {
+    actuators::devices(&config.ac).into_iter()
+        .find(|d| d.1 == rsrc)
+        .map(|d| d.0)
+        .ok_or(format!("Unknown resource {}", rsrc))
+}
+
+async fn evaluate(args: &Args, config: &Config, notifier: Arc<Notifier>) -> Result<Arc<HvacState>, String> {
+    let weather = weather(args, notifier);
+    let hvac_state = Arc::new(HvacState::new(config.blend.clone()));
+    hvac_state.load_history(&weather).await?;
+    hvac_state.refresh(&weather).await;
+    if hvac_state.current_state().is_none() {
+        return Err("Not enough temperature data to evaluate heating/cooling state".to_string());
+    }
+    Ok(hvac_state)
+}
+
+pub async fn devices(config: &Config) -> Result<(), String> {
+    let inventory = coap::Inventory::new(config.inventory.clone());
+    if let Err(e) = inventory.refresh().await {
+        println!("Error discovering services: {}", e);
+    }
+
+    let managed = actuators::devices(&config.ac);
+    println!("{:<12} {:<40} {:<12} {:<26} MANAGED", "NAME", "ADDRESS", "TYPE", "LAST SEEN");
+    for (name, device) in inventory.get_all().await {
+        let kind = managed.iter().find(|d| d.1 == name).map(|d| format!("{:?}", d.0)).unwrap_or_default();
+        println!("{:<12} {:<40} {:<12} {:<26} {}",
+                 name, device.addr, device.rsrc_type,
+                 format_time(device.last_seen.into()), kind);
+    }
+
+    for rsrc in inventory.missing(managed.iter().map(|d| d.1.as_str())).await {
+        println!("Managed resource {} was never discovered", rsrc);
+    }
+    Ok(())
+}
+
+pub async fn status(config: &Config, resources: &[String]) -> Result<(), String> {
+    let devices = actuators::devices(&config.ac);
+    for rsrc in resources {
+        if !devices.iter().any(|d| &d.1 == rsrc) {
+            println!("Unknown resource {}", rsrc);
+        }
+    }
+    let devices: Vec<_> = devices.into_iter()
+        .filter(|d| resources.is_empty() || resources.contains(&d.1))
+        .collect();
+
+    let reports = coap::DeviceReport::read_all(&devices).await;
+    println!("{}", serde_json::to_string_pretty(&reports).map_err(|e| e.to_string())?);
+    Ok(())
+}
+
+pub async fn set(config: &Config, rsrc: &str, payload: &str) -> Result<(), String> {
+    match device_kind(config, rsrc)? {
+        DeviceKind::Ac => {
+            let setting = serde_json::from_str(payload).map_err(|e| format!("Invalid AC setting: {}", e))?;
+            actuators::Ac::set_ac(rsrc, setting).await
+        },
+        DeviceKind::FloorHeating => {
+            let temperature = Decimal::from_str(payload).map_err(|e| format!("Invalid temperature: {}", e))?;
+            actuators::FloorHeating::set_temperature(rsrc, &temperature).await
+        },
+        DeviceKind::Leds => {
+            let values = payload.split(',')
+                .map(|v| v.trim().parse::<u16>().map_err(|e| format!("Invalid color component {}: {}", v, e)))
+                .collect::<Result<Vec<u16>, String>>()?;
+            match values[..] {
+                [r, g, b, w] => actuators::Leds::set_led(rsrc, (r, g, b, w)).await,
+                _ => Err(format!("Expected r,g,b,w, got {}", payload)),
+            }
+        },
+        DeviceKind::Shades => {
+            let position = payload.parse::<u16>().map_err(|e| format!("Invalid position: {}", e))?;
+            actuators::Shades::move_shades(rsrc, position).await
+        },
+    }
+}
+
+pub async fn get(config: &Config, rsrc: &str) -> Result<(), String> {
+    let status = DeviceStatus::read(device_kind(config, rsrc)?, rsrc).await?;
+    println!("{}", serde_json::to_string_pretty(&status).map_err(|e| e.to_string())?);
+    Ok(())
+}
+
+pub async fn state(args: &Args, config: &Config) -> Result<(), String> {
+    let hvac_state = evaluate(args, config, notifier()).await?;
+    println!("{}", serde_json::to_string_pretty(&hvac_state.get_decisions().await).map_err(|e| e.to_string())?);
+    Ok(())
+}
+
+pub async fn schedule(args: &Args, config: &Config, days: u32, json: bool) -> Result<(), String> {
+    let notifier = notifier();
+    let hvac_state = evaluate(args, config, notifier.clone()).await?;
+    let overrides = Arc::new(Overrides::new(config.overrides.clone(), notifier.clone()));
+    let cp = CronProcessor::new(overrides, notifier.clone(), Arc::new(Schedules::new()));
+    let twilight = Arc::new(web::Twilight::new(notifier.clone()));
+    let presence = Arc::new(Presence::new(config.presence.clone(), notifier.clone()));
+    let occupancy = Arc::new(Occupancy::new(config.occupancy.clone(), twilight.clone(), presence));
+
+    let mut planned = Vec::new();
+    let ac = actuators::Ac::new(cp.clone(), hvac_state.clone(), config.ac.clone(), twilight.clone(), occupancy.clone());
+    planned.extend(CronProcessor::preview(|after| ac.get_action_list(after), days).await);
+    let floor_heating = actuators::FloorHeating::new(cp.clone(), hvac_state.clone(), config.floor_heating.clone(), occupancy.clone());
+    planned.extend(CronProcessor::preview(|after| floor_heating.get_action_list(after), days).await);
+    let shades = actuators::Shades::new(cp.clone(), hvac_state.clone(), Arc::new(weather(args, notifier.clone())), twilight.clone(), config.shades.clone());
+    planned.extend(CronProcessor::preview(|after| shades.get_action_list(after), days).await);
+    match &args.qweather_key {
+        Some(key) => {
+            let leds = actuators::Leds::new(cp, Arc::new(web::Moon::new(key, notifier)), twilight, config.leds.clone(), occupancy);
+            planned.extend(CronProcessor::preview(|after| leds.get_action_list(after), days).await);
+        },
+        None => log::warn!("Skipping LED schedule without qweather key"),
+    }
+    planned.sort_by_key(|p| p.time);
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&planned).map_err(|e| e.to_string())?);
+    } else {
+        print_planned(&planned);
+    }
+    Ok(())
+}
+
+fn print_planned(planned: &[Planned]) {
+    for action in planned {
+        let targets: Vec<_> = action.description.targets.iter()
+            .map(|t| format!("{}={}", t.resource, t.value))
+            .collect();
+        println!("{}  {:<14} {}", action.time.format("%Y-%m-%d %H:%M:%S"), action.description.actuator, targets.join(" "));
+        for condition in &action.description.conditions {
+            println!("{:<21}{:<14} if {}", "", "", condition);
+        }
+    }
+}
+
+pub async fn moon(args: &Args) -> Result<(), String> {
+    let key = args.qweather_key.as_ref().ok_or("Missing qweather key")?;
+    let phase = web::Moon::new(key, notifier()).get_phase().await?;
+    println!("Moon phase: {}", phase);
+    Ok(())
+}
+
+pub async fn twilight() -> Result<(), String> {
+    let [begin, end] = web::Twilight::new(notifier()).get_pair().await?;
+    println!("Next civil twilight begin: {}", format_time(begin));
+    println!("Next civil twilight end:   {}", format_time(end));
+    Ok(())
+}
+
+pub async fn forecast(args: &Args, hours: u64) -> Result<(), String> {
+    let forecast = weather(args, notifier()).get_forecast(&Duration::from_secs(hours * 3600)).await?;
+    println!("Average temperature: {}", forecast.get_temperature());
+    println!("Average cloudiness:  {}%", forecast.get_cloudiness());
+    println!("Temperatures:        {:?}", forecast.get_temperatures());
+    Ok(())
+}
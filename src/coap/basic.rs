@@ -1,11 +1,53 @@
+use ciborium::value::Value;
 use home_mng::Coap;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::time::{Instant, SystemTime};
 
-use crate::coap::{CborMap, ServiceDiscovery};
+use crate::coap::ServiceDiscovery;
+use crate::metrics;
 
-pub async fn set_actuator(rsrc: &str, payload: CborMap) -> Result<(), String> {
+/// Payload successfully sent to a resource and when it was sent
+pub type Command = (Vec<(Value, Value)>, SystemTime);
+
+/// Last command sent to each resource
+static LAST_COMMANDS: Mutex<BTreeMap<String, Command>> = Mutex::new(BTreeMap::new());
+
+pub async fn set_actuator<T: Serialize>(rsrc: &str, payload: &T) -> Result<(), String> {
+    let payload = Value::serialized(payload).map_err(|e| e.to_string())?;
+    let start = Instant::now();
+    let coap = Coap::new();
+    let addr = ServiceDiscovery::new(&coap).discover_single(rsrc).await?;
+
+    let result = coap.set(&addr, rsrc, &payload).await;
+    metrics::coap_request(rsrc, "set", start.elapsed());
+    result.map_err(|e| e.to_string())?;
+
+    if let Value::Map(map) = payload {
+        LAST_COMMANDS.lock().unwrap().insert(rsrc.to_string(), (map, SystemTime::now()));
+    }
+    Ok(())
+}
+
+pub async fn get_actuator(rsrc: &str) -> Result<Vec<(Value, Value)>, String> {
+    let start = Instant::now();
     let coap = Coap::new();
     let addr = ServiceDiscovery::new(&coap).discover_single(rsrc).await?;
 
-    coap.set(&addr, rsrc, &payload.as_ciborium_map()).await
-        .map_err(|e| e.to_string())
+    let result = coap.get(&addr, rsrc, None).await;
+    metrics::coap_request(rsrc, "get", start.elapsed());
+    Ok(result
+        .map_err(|e| e.to_string())?
+        .ok_or(format!("No content returned by {}", rsrc))?
+        .as_cbor_map().ok_or(format!("Unexpected content returned by {}", rsrc))?
+        .clone())
+}
+
+pub fn last_command(rsrc: &str) -> Option<Command> {
+    LAST_COMMANDS.lock().unwrap().get(rsrc).cloned()
+}
+
+pub fn forget_command(rsrc: &str) {
+    LAST_COMMANDS.lock().unwrap().remove(rsrc);
 }
@@ -1,39 +1,187 @@
+use ciborium::value::{Integer, Value};
 use rust_decimal::prelude::*;
 
+const TAG_POSITIVE_BIGNUM: u64 = 2;
+const TAG_NEGATIVE_BIGNUM: u64 = 3;
+const TAG_DECIMAL_FRACTION: u64 = 4;
+const TAG_BIGFLOAT: u64 = 5;
+
 pub struct CborParser();
 
 impl CborParser {
-    pub fn to_decimal(value: &ciborium::value::Value) -> Result<Decimal, String> {
+    /// Decodes a number reported as an integer, a float, a bignum,
+    /// a decimal fraction (tag 4) or a bigfloat (tag 5)
+    pub fn to_decimal(value: &Value) -> Result<Decimal, String> {
         match value {
-            ciborium::value::Value::Tag(4, value) => {
-                match &**value {
-                    ciborium::value::Value::Array(vec) => {
-                        if vec.len() == 2 {
-                            if let (ciborium::value::Value::Integer(scale), ciborium::value::Value::Integer(num)) = (&vec[0], &vec[1]) {
-                                let num: i128 = (*num).try_into().unwrap();
-                                let scale: i128 = (*scale).try_into().unwrap();
-                                Ok(Decimal::new(num.try_into().unwrap(), (-scale).try_into().unwrap()))
-                            } else {
-                                Err("Unexpected type in decimal array".to_string())
-                            }
-                        } else {
-                            Err("Unexpcected number of entries in decimal array".to_string())
-                        }
-                    },
-                    _ => Err("Unexpected type in decimal tag".to_string())
+            Value::Integer(_) | Value::Tag(TAG_POSITIVE_BIGNUM, _) | Value::Tag(TAG_NEGATIVE_BIGNUM, _) => {
+                let num = Self::to_i128(value)?;
+                Decimal::from_i128(num).ok_or(format!("Integer {} out of decimal range", num))
+            },
+            Value::Float(num) => {
+                Decimal::from_f64(*num).ok_or(format!("Float {} out of decimal range", num))
+            },
+            Value::Tag(TAG_DECIMAL_FRACTION, value) => {
+                let (exponent, mantissa) = Self::to_exponent_mantissa(value)?;
+                if exponent <= 0 {
+                    let scale = u32::try_from(-exponent).map_err(|e| e.to_string())?;
+                    Decimal::try_from_i128_with_scale(mantissa, scale).map_err(|e| e.to_string())
+                } else {
+                    let exponent = u32::try_from(exponent).map_err(|e| e.to_string())?;
+                    10i128.checked_pow(exponent)
+                        .and_then(|multiplier| mantissa.checked_mul(multiplier))
+                        .and_then(Decimal::from_i128)
+                        .ok_or("Decimal fraction out of decimal range".to_string())
                 }
             },
+            Value::Tag(TAG_BIGFLOAT, value) => {
+                let (exponent, mantissa) = Self::to_exponent_mantissa(value)?;
+                let mantissa = Decimal::from_i128(mantissa).ok_or("Bigfloat mantissa out of decimal range")?;
+                let power = u32::try_from(exponent.unsigned_abs()).ok()
+                    .and_then(|exponent| 2i128.checked_pow(exponent))
+                    .and_then(Decimal::from_i128)
+                    .ok_or("Bigfloat exponent out of decimal range")?;
+                let result = if exponent >= 0 {
+                    mantissa.checked_mul(power)
+                } else {
+                    mantissa.checked_div(power)
+                };
+                result.ok_or("Bigfloat out of decimal range".to_string())
+            },
+            Value::Tag(tag, _) => Err(format!("Unexpected tag {} of a number", tag)),
             _ => Err("Unknown cbor type".to_string())
         }
     }
 
-    pub fn from_decimal(value: &Decimal) -> Result<ciborium::value::Value, std::num::TryFromIntError> {
-        use ciborium::value::{Value, Integer};
-
-        Ok(Value::Tag(4, Box::new(Value::Array(
-                [Value::Integer(Integer::from(-i32::try_from(value.scale())?)),
-                 Value::Integer(Integer::from(i64::try_from(value.mantissa())?))
+    pub fn from_decimal(value: &Decimal) -> Value {
+        Value::Tag(TAG_DECIMAL_FRACTION, Box::new(Value::Array(
+                [Value::Integer(Integer::from(-i64::from(value.scale()))),
+                 Self::from_i128(value.mantissa()),
                 ].to_vec()
-        ))))
+        )))
+    }
+
+    fn to_exponent_mantissa(value: &Value) -> Result<(i64, i128), String> {
+        match value {
+            Value::Array(vec) => {
+                if vec.len() == 2 {
+                    let exponent = i64::try_from(Self::to_i128(&vec[0])?).map_err(|e| e.to_string())?;
+                    Ok((exponent, Self::to_i128(&vec[1])?))
+                } else {
+                    Err("Unexpcected number of entries in decimal array".to_string())
+                }
+            },
+            _ => Err("Unexpected type in decimal tag".to_string())
+        }
+    }
+
+    fn to_i128(value: &Value) -> Result<i128, String> {
+        match value {
+            Value::Integer(num) => Ok(i128::from(*num)),
+            Value::Tag(tag @ (TAG_POSITIVE_BIGNUM | TAG_NEGATIVE_BIGNUM), bytes) => {
+                let bytes = bytes.as_bytes().ok_or("Bignum is not a byte string")?;
+                let leading_zeros = bytes.iter().take_while(|b| **b == 0).count();
+                let bytes = &bytes[leading_zeros..];
+                if bytes.len() > 16 {
+                    return Err("Bignum out of range".to_string());
+                }
+
+                let num = bytes.iter().fold(0u128, |acc, b| acc << 8 | u128::from(*b));
+                let num = i128::try_from(num).map_err(|_| "Bignum out of range".to_string())?;
+                // Negative bignum n encodes -1 - n
+                Ok(if *tag == TAG_POSITIVE_BIGNUM { num } else { -1 - num })
+            },
+            _ => Err("Unexpected type of integer".to_string()),
+        }
+    }
+
+    fn from_i128(value: i128) -> Value {
+        if let Ok(num) = Integer::try_from(value) {
+            return Value::Integer(num);
+        }
+
+        let (tag, num) = if value >= 0 {
+            (TAG_POSITIVE_BIGNUM, value as u128)
+        } else {
+            (TAG_NEGATIVE_BIGNUM, (-1 - value) as u128)
+        };
+        let bytes = num.to_be_bytes().into_iter().skip_while(|b| *b == 0).collect();
+        Value::Tag(tag, Box::new(Value::Bytes(bytes)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal_macros::dec;
+
+    use super::*;
+
+    fn tagged(tag: u64, exponent: i64, mantissa: Value) -> Value {
+        Value::Tag(tag, Box::new(Value::Array(vec![Value::from(exponent), mantissa])))
+    }
+
+    fn bignum(tag: u64, bytes: &[u8]) -> Value {
+        Value::Tag(tag, Box::new(Value::Bytes(bytes.to_vec())))
+    }
+
+    #[test]
+    fn integer() {
+        assert_eq!(CborParser::to_decimal(&Value::from(21)), Ok(dec!(21)));
+        assert_eq!(CborParser::to_decimal(&Value::from(-3)), Ok(dec!(-3)));
+        assert_eq!(CborParser::to_decimal(&Value::from(u64::MAX)), Ok(Decimal::from(u64::MAX)));
+    }
+
+    #[test]
+    fn float() {
+        assert_eq!(CborParser::to_decimal(&Value::Float(21.5)), Ok(dec!(21.5)));
+        assert_eq!(CborParser::to_decimal(&Value::Float(-0.25)), Ok(dec!(-0.25)));
+        assert!(CborParser::to_decimal(&Value::Float(f64::NAN)).is_err());
+        assert!(CborParser::to_decimal(&Value::Float(1e30)).is_err());
+    }
+
+    #[test]
+    fn bignum_integers() {
+        // 2^64 and -1 - 2^64
+        let bytes = [1, 0, 0, 0, 0, 0, 0, 0, 0];
+        assert_eq!(CborParser::to_decimal(&bignum(2, &bytes)), Ok(Decimal::from(1u128 << 64)));
+        assert_eq!(CborParser::to_decimal(&bignum(3, &bytes)), Ok(-Decimal::from(1u128 << 64) - dec!(1)));
+        // Leading zeros do not count towards the length
+        let mut padded = vec![0; 8];
+        padded.extend(bytes);
+        assert_eq!(CborParser::to_decimal(&bignum(2, &padded)), Ok(Decimal::from(1u128 << 64)));
+        assert!(CborParser::to_decimal(&bignum(2, &[1; 17])).is_err());
+        assert!(CborParser::to_decimal(&Value::Tag(2, Box::new(Value::from(1)))).is_err());
+    }
+
+    #[test]
+    fn decimal_fraction() {
+        assert_eq!(CborParser::to_decimal(&tagged(4, -2, Value::from(2150))), Ok(dec!(21.50)));
+        assert_eq!(CborParser::to_decimal(&tagged(4, 3, Value::from(-12))), Ok(dec!(-12000)));
+        assert_eq!(CborParser::to_decimal(&tagged(4, -28, bignum(2, &[1, 0, 0, 0, 0, 0, 0, 0, 0]))),
+                   Ok(Decimal::from_i128_with_scale(1 << 64, 28)));
+        assert!(CborParser::to_decimal(&tagged(4, -29, Value::from(1))).is_err());
+        assert!(CborParser::to_decimal(&tagged(4, 40, Value::from(1))).is_err());
+        assert!(CborParser::to_decimal(&Value::Tag(4, Box::new(Value::Array(vec![Value::from(1)])))).is_err());
+    }
+
+    #[test]
+    fn decimal_fraction_round_trip() {
+        for value in [dec!(21.5), dec!(-12.25), dec!(0.0000000000000000000000000001), Decimal::MAX, Decimal::MIN] {
+            let decoded = CborParser::to_decimal(&CborParser::from_decimal(&value)).unwrap();
+            assert_eq!(decoded, value);
+            assert_eq!(decoded.scale(), value.scale());
+        }
+    }
+
+    #[test]
+    fn bigfloat() {
+        assert_eq!(CborParser::to_decimal(&tagged(5, -1, Value::from(3))), Ok(dec!(1.5)));
+        assert_eq!(CborParser::to_decimal(&tagged(5, 4, Value::from(-3))), Ok(dec!(-48)));
+        assert!(CborParser::to_decimal(&tagged(5, 200, Value::from(1))).is_err());
+    }
+
+    #[test]
+    fn unexpected() {
+        assert!(CborParser::to_decimal(&Value::Tag(6, Box::new(Value::from(1)))).is_err());
+        assert!(CborParser::to_decimal(&Value::Text("1".to_string())).is_err());
     }
 }
@@ -0,0 +1,111 @@
+use chrono::prelude::*;
+use home_mng::Coap;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::coap::ServiceDiscovery;
+
+#[derive(Clone, Deserialize)]
+#[serde(default)]
+pub struct InventoryConfig {
+    pub path: PathBuf,
+    pub refresh_minutes: u64,
+}
+
+impl Default for InventoryConfig {
+    fn default() -> Self {
+        Self {
+            path: PathBuf::from("/var/lib/home_cron/inventory.json"),
+            refresh_minutes: 60,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Device {
+    pub addr: SocketAddr,
+    pub rsrc_type: String,
+    pub last_seen: DateTime<Utc>,
+}
+
+/// Every service ever discovered on the network, persisted between runs
+pub struct Inventory {
+    config: InventoryConfig,
+    devices: tokio::sync::Mutex<BTreeMap<String, Device>>,
+}
+
+impl Inventory {
+    pub fn new(config: InventoryConfig) -> Self {
+        let devices = match Self::load(&config.path) {
+            Ok(devices) => devices,
+            Err(e) => {
+                log::warn!("Starting with empty device inventory: {}", e);
+                BTreeMap::new()
+            },
+        };
+
+        Self {
+            config,
+            devices: tokio::sync::Mutex::new(devices),
+        }
+    }
+
+    fn load(path: &Path) -> Result<BTreeMap<String, Device>, String> {
+        let file = std::fs::File::open(path).map_err(|e| format!("Cannot open {}: {}", path.display(), e))?;
+        serde_json::from_reader(std::io::BufReader::new(file)).map_err(|e| format!("Cannot parse {}: {}", path.display(), e))
+    }
+
+    fn save(&self, devices: &BTreeMap<String, Device>) -> Result<(), String> {
+        let path = &self.config.path;
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).map_err(|e| format!("Cannot create {}: {}", dir.display(), e))?;
+        }
+        let file = std::fs::File::create(path).map_err(|e| format!("Cannot create {}: {}", path.display(), e))?;
+        serde_json::to_writer_pretty(file, devices).map_err(|e| format!("Cannot write {}: {}", path.display(), e))
+    }
+
+    pub async fn get_all(&self) -> BTreeMap<String, Device> {
+        self.devices.lock().await.clone()
+    }
+
+    /// Discovers all services on the network and records them. Failing to save the
+    /// inventory is logged and does not fail the discovery
+    pub async fn refresh(&self) -> Result<(), String> {
+        let coap = Coap::new();
+        let services = ServiceDiscovery::new(&coap).discover_all().await?;
+        let now = Utc::now();
+
+        let mut devices = self.devices.lock().await;
+        for service in services {
+            devices.insert(service.name, Device {
+                addr: service.addr,
+                rsrc_type: service.rsrc_type,
+                last_seen: now,
+            });
+        }
+        if let Err(e) = self.save(&devices) {
+            log::warn!(path:% = self.config.path.display(); "Error saving device inventory: {}", e);
+        }
+        Ok(())
+    }
+
+    /// Names of `rsrcs` which were never discovered
+    pub async fn missing<'a>(&self, rsrcs: impl Iterator<Item = &'a str>) -> Vec<&'a str> {
+        let devices = self.devices.lock().await;
+        rsrcs.filter(|r| !devices.contains_key(*r)).collect()
+    }
+
+    /// Refreshes the inventory periodically. The first refresh is expected to be done by the caller
+    pub async fn process(&self) {
+        loop {
+            tokio::time::sleep(Duration::from_secs(self.config.refresh_minutes * 60)).await;
+
+            if let Err(e) = self.refresh().await {
+                log::warn!("Error refreshing device inventory: {}", e);
+            }
+        }
+    }
+}
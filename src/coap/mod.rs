@@ -1,10 +1,16 @@
 pub mod basic;
-mod cbor_map;
 mod cbor_parser;
+mod inventory;
+mod observe;
+mod payload;
 mod service_discovery;
+mod status;
 mod weather;
 
-pub use cbor_map::CborMap;
 pub use cbor_parser::CborParser;
+pub use inventory::{Inventory, InventoryConfig};
+pub use observe::{Notification, ObserveConfig, Observer};
+pub use payload::{AcCommand, FloorHeatingCommand, LedCommand, ShadeCommand};
 pub use service_discovery::ServiceDiscovery;
+pub use status::{AcStatus, DeviceKind, DeviceReport, DeviceStatus};
 pub use weather::Weather;
@@ -0,0 +1,253 @@
+use ciborium::value::Value;
+use home_mng::Coap;
+use serde::Deserialize;
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::net::UdpSocket;
+use tokio::sync::broadcast;
+
+use crate::coap::ServiceDiscovery;
+
+const VERSION: u8 = 1;
+const TYPE_CON: u8 = 0;
+const TYPE_ACK: u8 = 2;
+const CODE_GET: u8 = 0x01;
+const OPTION_OBSERVE: u16 = 6;
+const OPTION_URI_PATH: u16 = 11;
+const OPTION_ACCEPT: u16 = 17;
+const CONTENT_FORMAT_CBOR: u16 = 60;
+const PAYLOAD_MARKER: u8 = 0xff;
+
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(10);
+const REREGISTER_PERIOD: Duration = Duration::from_secs(600);
+const RETRY_PERIOD: Duration = Duration::from_secs(60);
+
+#[derive(Clone, Deserialize)]
+#[serde(default)]
+pub struct ObserveConfig {
+    /// Observe the outdoor temperature instead of polling it
+    pub sensors: bool,
+    /// Observe state of all managed actuators
+    pub actuators: bool,
+}
+
+impl Default for ObserveConfig {
+    fn default() -> Self {
+        Self {
+            sensors: true,
+            actuators: true,
+        }
+    }
+}
+
+/// Content of a resource pushed by the server
+#[derive(Clone, Debug)]
+pub struct Notification {
+    pub path: String,
+    pub content: Vec<(Value, Value)>,
+}
+
+struct Message {
+    msg_type: u8,
+    code: u8,
+    mid: u16,
+    token: Vec<u8>,
+    observe: Option<u32>,
+    payload: Vec<u8>,
+}
+
+impl Message {
+    fn encode_option(buf: &mut Vec<u8>, delta: u16, value: &[u8]) {
+        fn nibble(val: usize) -> (u8, Vec<u8>) {
+            if val < 13 {
+                (val as u8, Vec::new())
+            } else if val < 269 {
+                (13, vec![(val - 13) as u8])
+            } else {
+                (14, ((val - 269) as u16).to_be_bytes().to_vec())
+            }
+        }
+
+        let (delta_nibble, delta_ext) = nibble(delta.into());
+        let (len_nibble, len_ext) = nibble(value.len());
+        buf.push(delta_nibble << 4 | len_nibble);
+        buf.extend(delta_ext);
+        buf.extend(len_ext);
+        buf.extend_from_slice(value);
+    }
+
+    fn uint(val: u16) -> Vec<u8> {
+        val.to_be_bytes().into_iter().skip_while(|b| *b == 0).collect()
+    }
+
+    fn observe_request(mid: u16, token: &[u8], path: &str) -> Vec<u8> {
+        let mut buf = vec![VERSION << 6 | TYPE_CON << 4 | token.len() as u8, CODE_GET];
+        buf.extend(mid.to_be_bytes());
+        buf.extend_from_slice(token);
+
+        // Register: the Observe option with value 0 is sent as an empty one
+        Self::encode_option(&mut buf, OPTION_OBSERVE, &[]);
+        let mut last_option = OPTION_OBSERVE;
+        for segment in path.split('/').filter(|s| !s.is_empty()) {
+            Self::encode_option(&mut buf, OPTION_URI_PATH - last_option, segment.as_bytes());
+            last_option = OPTION_URI_PATH;
+        }
+        Self::encode_option(&mut buf, OPTION_ACCEPT - last_option, &Self::uint(CONTENT_FORMAT_CBOR));
+
+        buf
+    }
+
+    fn ack(mid: u16) -> Vec<u8> {
+        let mut buf = vec![VERSION << 6 | TYPE_ACK << 4, 0];
+        buf.extend(mid.to_be_bytes());
+        buf
+    }
+
+    fn decode(data: &[u8]) -> Result<Self, String> {
+        if data.len() < 4 {
+            return Err("Message shorter than header".to_string());
+        }
+        if data[0] >> 6 != VERSION {
+            return Err("Unsupported CoAP version".to_string());
+        }
+
+        let token_len = usize::from(data[0] & 0x0f);
+        let token = data.get(4..4 + token_len).ok_or("Truncated token")?.to_vec();
+        let mut message = Self {
+            msg_type: (data[0] >> 4) & 0x03,
+            code: data[1],
+            mid: u16::from_be_bytes([data[2], data[3]]),
+            token,
+            observe: None,
+            payload: Vec::new(),
+        };
+
+        let mut pos = 4 + token_len;
+        let mut option = 0u32;
+        let read_ext = |pos: &mut usize, nibble: u8| -> Result<u32, String> {
+            match nibble {
+                0..=12 => Ok(nibble.into()),
+                13 => {
+                    let val = *data.get(*pos).ok_or("Truncated option")?;
+                    *pos += 1;
+                    Ok(u32::from(val) + 13)
+                },
+                14 => {
+                    let val = data.get(*pos..*pos + 2).ok_or("Truncated option")?;
+                    *pos += 2;
+                    Ok(u32::from(u16::from_be_bytes([val[0], val[1]])) + 269)
+                },
+                _ => Err("Reserved option nibble".to_string()),
+            }
+        };
+
+        while pos < data.len() {
+            if data[pos] == PAYLOAD_MARKER {
+                message.payload = data[pos + 1..].to_vec();
+                break;
+            }
+
+            let header = data[pos];
+            pos += 1;
+            option += read_ext(&mut pos, header >> 4)?;
+            let len = read_ext(&mut pos, header & 0x0f)? as usize;
+            let value = data.get(pos..pos + len).ok_or("Truncated option value")?;
+            pos += len;
+
+            if option == u32::from(OPTION_OBSERVE) {
+                message.observe = Some(value.iter().fold(0u32, |acc, b| acc << 8 | u32::from(*b)));
+            }
+        }
+
+        Ok(message)
+    }
+}
+
+/// Client of CoAP Observe (RFC 7641) broadcasting every notification received
+pub struct Observer {
+    notifications: broadcast::Sender<Notification>,
+}
+
+impl Observer {
+    pub fn new() -> Self {
+        Self {
+            notifications: broadcast::channel(64).0,
+        }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Notification> {
+        self.notifications.subscribe()
+    }
+
+    /// Observes `path` of the service `rsrc` until the server turns out not to support observing it
+    pub async fn observe(&self, rsrc: &str, path: &str) {
+        loop {
+            match self.observe_once(rsrc, path).await {
+                Ok(()) => {
+                    log::warn!(resource = rsrc; "Observing {} is not supported", path);
+                    return;
+                },
+                Err(e) => log::warn!(resource = rsrc; "Error observing {}: {}", path, e),
+            }
+
+            tokio::time::sleep(RETRY_PERIOD).await;
+        }
+    }
+
+    async fn observe_once(&self, rsrc: &str, path: &str) -> Result<(), String> {
+        let coap = Coap::new();
+        let addr = ServiceDiscovery::new(&coap).discover_single(rsrc).await?;
+        let local_addr: SocketAddr = if addr.is_ipv6() { "[::]:0" } else { "0.0.0.0:0" }.parse().unwrap();
+        let socket = UdpSocket::bind(local_addr).await.map_err(|e| e.to_string())?;
+        socket.connect(addr).await.map_err(|e| e.to_string())?;
+
+        let token: [u8; 4] = rand::random();
+        let mut buf = [0u8; 1280];
+
+        loop {
+            // Registration is refreshed periodically in case the server forgot about us
+            socket.send(&Message::observe_request(rand::random(), &token, path)).await.map_err(|e| e.to_string())?;
+            let mut registered = false;
+            let reregister_time = tokio::time::Instant::now() + REREGISTER_PERIOD;
+
+            loop {
+                let deadline = if registered { reregister_time } else { tokio::time::Instant::now() + RESPONSE_TIMEOUT };
+                let len = match tokio::time::timeout_at(deadline, socket.recv(&mut buf)).await {
+                    Ok(len) => len.map_err(|e| e.to_string())?,
+                    Err(_) if registered => break,
+                    Err(_) => return Err(format!("No response from {}", addr)),
+                };
+
+                let message = match Message::decode(&buf[..len]) {
+                    Ok(message) => message,
+                    Err(e) => {
+                        log::debug!("Dropping malformed message from {}: {}", addr, e);
+                        continue;
+                    },
+                };
+                if message.msg_type == TYPE_CON {
+                    socket.send(&Message::ack(message.mid)).await.map_err(|e| e.to_string())?;
+                }
+                if message.token != token {
+                    continue;
+                }
+                if message.code >> 5 != 2 {
+                    return Err(format!("Response code {}.{:02}", message.code >> 5, message.code & 0x1f));
+                }
+                if message.observe.is_none() {
+                    return Ok(());
+                }
+                registered = true;
+
+                let content = ciborium::de::from_reader::<Value, _>(&message.payload[..])
+                    .map_err(|e| e.to_string())?
+                    .as_map().ok_or(format!("Unexpected content observed from {}", path))?
+                    .clone();
+                let _ = self.notifications.send(Notification {
+                    path: path.to_string(),
+                    content,
+                });
+            }
+        }
+    }
+}
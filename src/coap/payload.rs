@@ -0,0 +1,164 @@
+use rust_decimal::prelude::*;
+use rust_decimal_macros::dec;
+use serde::{Deserialize, Serialize};
+
+const AC_TEMPERATURE_RANGE: std::ops::RangeInclusive<u8> = 16..=31;
+const FLOOR_HEATING_TEMPERATURE_RANGE: std::ops::RangeInclusive<Decimal> = dec!(5)..=dec!(35);
+const SHADE_POSITION_MAX: u16 = 256;
+
+/// Serde codec of Decimal as CBOR decimal fraction (tag 4)
+mod decimal_fraction {
+    use rust_decimal::prelude::*;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    use crate::coap::CborParser;
+
+    pub fn serialize<S: Serializer>(value: &Decimal, serializer: S) -> Result<S::Ok, S::Error> {
+        CborParser::from_decimal(value).serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Decimal, D::Error> {
+        let value = ciborium::value::Value::deserialize(deserializer)?;
+        CborParser::to_decimal(&value).map_err(serde::de::Error::custom)
+    }
+}
+
+fn ascii(val: char) -> Result<u8, String> {
+    if val.is_ascii() {
+        Ok(val as u8)
+    } else {
+        Err(format!("{:?} is not an ASCII character", val))
+    }
+}
+
+#[derive(Debug, Deserialize, PartialEq, Serialize)]
+pub struct AcCommand {
+    #[serde(rename = "o")]
+    pub on: bool,
+    #[serde(rename = "f")]
+    pub fan: u8,
+    #[serde(rename = "t")]
+    pub temperature: u8,
+    #[serde(rename = "m")]
+    pub mode: u8,
+}
+
+impl AcCommand {
+    pub fn new(on: bool, mode: char, fan: char, temperature: u8) -> Result<Self, String> {
+        if !AC_TEMPERATURE_RANGE.contains(&temperature) {
+            return Err(format!("AC temperature {} out of range {:?}", temperature, AC_TEMPERATURE_RANGE));
+        }
+
+        Ok(Self {
+            on,
+            fan: ascii(fan)?,
+            temperature,
+            mode: ascii(mode)?,
+        })
+    }
+}
+
+#[derive(Debug, Deserialize, PartialEq, Serialize)]
+pub struct FloorHeatingCommand {
+    #[serde(rename = "s", with = "decimal_fraction")]
+    pub setpoint: Decimal,
+}
+
+impl FloorHeatingCommand {
+    pub fn new(setpoint: Decimal) -> Result<Self, String> {
+        if !FLOOR_HEATING_TEMPERATURE_RANGE.contains(&setpoint) {
+            return Err(format!("Floor heating temperature {} out of range {:?}", setpoint, FLOOR_HEATING_TEMPERATURE_RANGE));
+        }
+
+        Ok(Self {
+            setpoint,
+        })
+    }
+}
+
+#[derive(Debug, Deserialize, PartialEq, Serialize)]
+pub struct LedCommand {
+    pub r: u16,
+    pub g: u16,
+    pub b: u16,
+    pub w: u16,
+}
+
+#[derive(Debug, Deserialize, PartialEq, Serialize)]
+pub struct ShadeCommand {
+    #[serde(rename = "val")]
+    pub position: u16,
+}
+
+impl ShadeCommand {
+    pub fn new(position: u16) -> Result<Self, String> {
+        if position > SHADE_POSITION_MAX {
+            return Err(format!("Shade position {} above {}", position, SHADE_POSITION_MAX));
+        }
+
+        Ok(Self {
+            position,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ciborium::value::Value;
+
+    use super::*;
+
+    fn round_trip<T: Serialize + for<'de> Deserialize<'de>>(payload: &T) -> T {
+        let mut bytes = Vec::new();
+        ciborium::ser::into_writer(payload, &mut bytes).unwrap();
+        ciborium::de::from_reader(bytes.as_slice()).unwrap()
+    }
+
+    #[test]
+    fn decimal_fraction_round_trip() {
+        let values = [
+            dec!(21.5),
+            dec!(-12.25),
+            dec!(0),
+            dec!(0.0000000000000000000000000001),
+            dec!(-7.9228162514264337593543950335),
+            Decimal::MAX,
+            Decimal::MIN,
+        ];
+        for setpoint in values {
+            let command = FloorHeatingCommand { setpoint };
+            let decoded = round_trip(&command);
+            assert_eq!(decoded, command);
+            assert_eq!(decoded.setpoint.scale(), setpoint.scale());
+        }
+    }
+
+    #[test]
+    fn decimal_fraction_is_tag_4() {
+        let value = Value::serialized(&FloorHeatingCommand::new(dec!(22.5)).unwrap()).unwrap();
+        let map = value.as_map().unwrap();
+        assert_eq!(map[0].1, Value::Tag(4, Box::new(Value::Array(vec![Value::from(-1), Value::from(225)]))));
+    }
+
+    #[test]
+    fn commands_round_trip() {
+        let ac = AcCommand::new(true, 'c', 'a', 24).unwrap();
+        assert_eq!(round_trip(&ac), ac);
+        let led = LedCommand { r: 160, g: 180, b: 210, w: 65535 };
+        assert_eq!(round_trip(&led), led);
+        let shade = ShadeCommand::new(256).unwrap();
+        assert_eq!(round_trip(&shade), shade);
+    }
+
+    #[test]
+    fn out_of_range() {
+        assert!(AcCommand::new(true, 'c', 'a', 15).is_err());
+        assert!(AcCommand::new(true, 'c', 'a', 32).is_err());
+        assert!(AcCommand::new(true, 'ć', 'a', 24).is_err());
+        assert!(AcCommand::new(true, 'c', 'ł', 24).is_err());
+        assert!(FloorHeatingCommand::new(dec!(4.9)).is_err());
+        assert!(FloorHeatingCommand::new(dec!(35.1)).is_err());
+        assert!(FloorHeatingCommand::new(dec!(35)).is_ok());
+        assert!(ShadeCommand::new(257).is_err());
+    }
+}
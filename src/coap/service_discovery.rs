@@ -1,6 +1,12 @@
 use home_mng::Coap;
 use std::net::SocketAddr;
 
+pub struct Service {
+    pub name: String,
+    pub addr: SocketAddr,
+    pub rsrc_type: String,
+}
+
 pub struct ServiceDiscovery<'a> {
     coap: &'a Coap,
 }
@@ -18,4 +24,16 @@ impl <'a> ServiceDiscovery<'a> {
             .ok_or(format!("Could not discover address of {}", rsrc))?
             .0)
     }
+
+    pub async fn discover_all(&self) -> Result<Vec<Service>, String> {
+        Ok(self.coap.service_discovery(None, None).await
+            .map_err(|e| e.to_string())?
+            .into_iter()
+            .map(|(name, (addr, rsrc_type))| Service {
+                name,
+                addr,
+                rsrc_type,
+            })
+            .collect())
+    }
 }
@@ -0,0 +1,124 @@
+use ciborium::value::Value;
+use rust_decimal::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::coap::{basic, CborParser};
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DeviceKind {
+    Ac,
+    FloorHeating,
+    Leds,
+    Shades,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AcStatus {
+    pub on: bool,
+    pub mode: char,
+    pub fan: char,
+    pub temperature: Decimal,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FloorHeatingStatus {
+    pub setpoint: Decimal,
+    pub temperature: Option<Decimal>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct LedStatus {
+    pub r: u16,
+    pub g: u16,
+    pub b: u16,
+    pub w: u16,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ShadeStatus {
+    pub position: u16,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DeviceStatus {
+    Ac(AcStatus),
+    FloorHeating(FloorHeatingStatus),
+    Leds(LedStatus),
+    Shades(ShadeStatus),
+}
+
+#[derive(Debug, Serialize)]
+pub struct DeviceReport {
+    pub resource: String,
+    pub kind: DeviceKind,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<DeviceStatus>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl DeviceReport {
+    pub async fn read_all(devices: &[(DeviceKind, String)]) -> Vec<Self> {
+        let reads = devices.iter().map(|(kind, rsrc)| async move {
+            let result = DeviceStatus::read(*kind, rsrc).await;
+            Self {
+                resource: rsrc.clone(),
+                kind: *kind,
+                error: result.as_ref().err().cloned(),
+                status: result.ok(),
+            }
+        });
+
+        futures::future::join_all(reads).await
+    }
+}
+
+impl DeviceStatus {
+    pub async fn read(kind: DeviceKind, rsrc: &str) -> Result<Self, String> {
+        let map = basic::get_actuator(rsrc).await?;
+        Self::parse(kind, &map).map_err(|e| format!("Cannot parse state of {}: {}", rsrc, e))
+    }
+
+    fn parse(kind: DeviceKind, map: &[(Value, Value)]) -> Result<Self, String> {
+        Ok(match kind {
+            DeviceKind::Ac => DeviceStatus::Ac(AcStatus {
+                on: Self::field(map, "o")?.as_bool().ok_or("\"o\" is not a bool")?,
+                mode: Self::to_char(Self::field(map, "m")?)?,
+                fan: Self::to_char(Self::field(map, "f")?)?,
+                temperature: CborParser::to_decimal(Self::field(map, "t")?)?,
+            }),
+            DeviceKind::FloorHeating => DeviceStatus::FloorHeating(FloorHeatingStatus {
+                setpoint: CborParser::to_decimal(Self::field(map, "s")?)?,
+                temperature: Self::field(map, "t").ok().map(CborParser::to_decimal).transpose()?,
+            }),
+            DeviceKind::Leds => DeviceStatus::Leds(LedStatus {
+                r: Self::to_u16(Self::field(map, "r")?)?,
+                g: Self::to_u16(Self::field(map, "g")?)?,
+                b: Self::to_u16(Self::field(map, "b")?)?,
+                w: Self::to_u16(Self::field(map, "w")?)?,
+            }),
+            DeviceKind::Shades => DeviceStatus::Shades(ShadeStatus {
+                position: Self::to_u16(Self::field(map, "val")?)?,
+            }),
+        })
+    }
+
+    fn field<'a>(map: &'a [(Value, Value)], key: &str) -> Result<&'a Value, String> {
+        map.iter()
+            .find(|e| e.0.as_text().is_some_and(|t| t == key))
+            .map(|e| &e.1)
+            .ok_or(format!("Missing \"{}\"", key))
+    }
+
+    fn to_u16(value: &Value) -> Result<u16, String> {
+        let value: i128 = value.as_integer().ok_or("Value is not an integer")?.into();
+        u16::try_from(value).map_err(|e| e.to_string())
+    }
+
+    fn to_char(value: &Value) -> Result<char, String> {
+        let value: i128 = value.as_integer().ok_or("Value is not an integer")?.into();
+        Ok(char::from(u8::try_from(value).map_err(|e| e.to_string())?))
+    }
+}
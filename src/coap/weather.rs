@@ -1,3 +1,4 @@
+use ciborium::value::Value;
 use home_mng::Coap;
 use rust_decimal::prelude::*;
 
@@ -8,6 +9,9 @@ pub struct Weather {
 }
 
 impl Weather {
+    pub const RSRC: &'static str = "bac";
+    pub const PATH: &'static str = "bac/temp";
+
     pub fn new() -> Self {
         Weather {
             coap: Coap::new(),
@@ -15,11 +19,16 @@ impl Weather {
     }
 
     pub async fn get_temperature(&self) -> Result<Decimal, String> {
-        let addr = ServiceDiscovery::new(&self.coap).discover_single("bac").await?;
-        let mut temps = self.coap.get(&addr, "bac/temp", None).await
+        let addr = ServiceDiscovery::new(&self.coap).discover_single(Self::RSRC).await?;
+        let content = self.coap.get(&addr, Self::PATH, None).await
             .map_err(|e| e.to_string())?
-            .ok_or("No temperature content returned by bac/temp")?
-            .as_cbor_map().ok_or("Unexpected temperature content returned by bac/temp")?
+            .ok_or("No temperature content returned by bac/temp")?;
+
+        Self::parse_temperature(content.as_cbor_map().ok_or("Unexpected temperature content returned by bac/temp")?)
+    }
+
+    pub fn parse_temperature(map: &[(Value, Value)]) -> Result<Decimal, String> {
+        let mut temps = map
             .iter()
             .filter(|e| e.0.as_text()
                     .is_some_and(|t| t == "e"))
@@ -0,0 +1,51 @@
+use chrono_tz::Tz;
+use serde::Deserialize;
+use std::path::Path;
+
+use crate::actuators::{AcConfig, FloorHeatingConfig, LedsConfig, ShadesConfig, TransitionHook};
+use crate::calendar::CalendarConfig;
+use crate::coap::{InventoryConfig, ObserveConfig};
+use crate::logging::LoggingConfig;
+use crate::mqtt::{HomeAssistantConfig, MqttConfig};
+use crate::notify::NotifyConfig;
+use crate::state::{BlendConfig, HealthConfig, OccupancyConfig, OverrideConfig, PresenceConfig};
+
+/// Settings read from the JSON file passed with --config. Every section is optional.
+#[derive(Default, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub ac: AcConfig,
+    pub blend: BlendConfig,
+    pub calendar: CalendarConfig,
+    pub floor_heating: FloorHeatingConfig,
+    pub health: HealthConfig,
+    /// Home Assistant MQTT discovery. Requires the mqtt section
+    pub home_assistant: Option<HomeAssistantConfig>,
+    pub inventory: InventoryConfig,
+    pub leds: LedsConfig,
+    pub logging: LoggingConfig,
+    /// Broker connection. MQTT is disabled if not set
+    pub mqtt: Option<MqttConfig>,
+    pub notify: NotifyConfig,
+    pub observe: ObserveConfig,
+    /// Times rooms are in use, driving AC, floor heating and lights
+    pub occupancy: OccupancyConfig,
+    pub overrides: OverrideConfig,
+    /// Presence detection switching to away mode. Home is considered occupied if not set
+    pub presence: Option<PresenceConfig>,
+    pub shades: ShadesConfig,
+    /// Time zone of schedules, e.g. "Europe/Warsaw". The host time zone if not set
+    pub timezone: Option<Tz>,
+    pub transitions: Vec<TransitionHook>,
+}
+
+impl Config {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let file = std::fs::File::open(path)
+            .map_err(|e| format!("Cannot open config file {}: {}", path.display(), e))?;
+        let config: Self = serde_json::from_reader(std::io::BufReader::new(file))
+            .map_err(|e| format!("Cannot parse config file {}: {}", path.display(), e))?;
+        config.ac.validate().map_err(|e| format!("Invalid config file {}: {}", path.display(), e))?;
+        Ok(config)
+    }
+}
@@ -0,0 +1,56 @@
+use log::Record;
+use std::os::unix::net::UnixDatagram;
+
+use crate::logging::syslog_priority;
+
+const JOURNALD_SOCKET: &str = "/run/systemd/journal/socket";
+
+/// Client of the native journal protocol
+pub struct Journald {
+    socket: UnixDatagram,
+}
+
+impl Journald {
+    pub fn new() -> std::io::Result<Self> {
+        let socket = UnixDatagram::unbound()?;
+        socket.connect(JOURNALD_SOCKET)?;
+        Ok(Self {
+            socket,
+        })
+    }
+
+    pub fn send(&self, record: &Record, fields: &[(String, String)]) -> std::io::Result<()> {
+        let mut datagram = Vec::new();
+        Self::field(&mut datagram, "MESSAGE", &record.args().to_string());
+        Self::field(&mut datagram, "PRIORITY", &syslog_priority(record.level()).to_string());
+        Self::field(&mut datagram, "SYSLOG_IDENTIFIER", "home_cron");
+        Self::field(&mut datagram, "TARGET", record.target());
+        for (key, value) in fields {
+            Self::field(&mut datagram, &Self::field_name(key), value);
+        }
+
+        self.socket.send(&datagram)?;
+        Ok(())
+    }
+
+    /// Journal field names are upper case letters, digits and underscores, not starting with an underscore
+    fn field_name(key: &str) -> String {
+        let name: String = key.chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_uppercase() } else { '_' })
+            .collect();
+        name.trim_start_matches('_').to_string()
+    }
+
+    fn field(datagram: &mut Vec<u8>, name: &str, value: &str) {
+        datagram.extend_from_slice(name.as_bytes());
+        if value.contains('\n') {
+            // Multi-line values are length-prefixed instead of using "="
+            datagram.push(b'\n');
+            datagram.extend_from_slice(&(value.len() as u64).to_le_bytes());
+        } else {
+            datagram.push(b'=');
+        }
+        datagram.extend_from_slice(value.as_bytes());
+        datagram.push(b'\n');
+    }
+}
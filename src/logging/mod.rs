@@ -0,0 +1,183 @@
+mod journald;
+
+use chrono::prelude::*;
+use log::kv::{Key, Value, VisitSource};
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::str::FromStr;
+
+use journald::Journald;
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum LogFormat {
+    /// Human readable lines on stderr
+    Text,
+    /// One JSON object per line on stderr
+    Json,
+    /// Native systemd journal protocol, keeping the fields searchable
+    Journald,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            "journald" => Ok(LogFormat::Journald),
+            _ => Err(format!("Unknown log format {}", s)),
+        }
+    }
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(default)]
+pub struct LoggingConfig {
+    /// Level of modules not listed in `modules`
+    pub level: String,
+    /// Levels by module path prefix, e.g. "home_cron::coap": "debug"
+    pub modules: BTreeMap<String, String>,
+    pub format: LogFormat,
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        Self {
+            level: "info".to_string(),
+            modules: BTreeMap::new(),
+            format: LogFormat::Text,
+        }
+    }
+}
+
+impl LoggingConfig {
+    /// Applies a spec in the form "info,home_cron::coap=debug" on top of the configured levels
+    pub fn apply_spec(&mut self, spec: &str) {
+        for directive in spec.split(',').map(str::trim).filter(|d| !d.is_empty()) {
+            match directive.split_once('=') {
+                Some((module, level)) => {
+                    self.modules.insert(module.to_string(), level.to_string());
+                },
+                None => self.level = directive.to_string(),
+            }
+        }
+    }
+}
+
+/// Collects key-value fields attached to a record
+struct Fields(Vec<(String, String)>);
+
+impl<'kvs> VisitSource<'kvs> for Fields {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), log::kv::Error> {
+        self.0.push((key.to_string(), value.to_string()));
+        Ok(())
+    }
+}
+
+struct Logger {
+    level: LevelFilter,
+    /// Sorted from the longest prefix, so the most specific one matches first
+    modules: Vec<(String, LevelFilter)>,
+    format: LogFormat,
+    journald: Option<Journald>,
+}
+
+impl Logger {
+    fn level(&self, target: &str) -> LevelFilter {
+        self.modules.iter()
+            .find(|(module, _)| target == module || target.starts_with(&format!("{}::", module)))
+            .map(|(_, level)| *level)
+            .unwrap_or(self.level)
+    }
+
+    fn write_text(record: &Record, fields: &Fields) {
+        let mut line = format!("{} {:<5} {}: {}",
+                               Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ"), record.level(), record.target(), record.args());
+        for (key, value) in &fields.0 {
+            line.push_str(&format!(" {}={}", key, value));
+        }
+        let _ = writeln!(std::io::stderr(), "{}", line);
+    }
+
+    fn write_json(record: &Record, fields: &Fields) {
+        let mut object = serde_json::Map::new();
+        object.insert("time".to_string(), Utc::now().to_rfc3339().into());
+        object.insert("level".to_string(), record.level().as_str().into());
+        object.insert("target".to_string(), record.target().into());
+        object.insert("message".to_string(), record.args().to_string().into());
+        for (key, value) in &fields.0 {
+            object.insert(key.clone(), value.clone().into());
+        }
+        let _ = writeln!(std::io::stderr(), "{}", serde_json::Value::Object(object));
+    }
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level(metadata.target())
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let mut fields = Fields(Vec::new());
+        let _ = record.key_values().visit(&mut fields);
+
+        match (&self.format, &self.journald) {
+            (LogFormat::Journald, Some(journald)) => {
+                if journald.send(record, &fields.0).is_err() {
+                    Self::write_text(record, &fields);
+                }
+            },
+            (LogFormat::Json, _) => Self::write_json(record, &fields),
+            _ => Self::write_text(record, &fields),
+        }
+    }
+
+    fn flush(&self) {
+        let _ = std::io::stderr().flush();
+    }
+}
+
+fn parse_level(level: &str) -> Result<LevelFilter, String> {
+    LevelFilter::from_str(level).map_err(|_| format!("Unknown log level {}", level))
+}
+
+/// Installs the global logger. Errors if the configuration is invalid
+pub fn init(config: &LoggingConfig) -> Result<(), String> {
+    let level = parse_level(&config.level)?;
+    let mut modules = config.modules.iter()
+        .map(|(module, level)| Ok((module.clone(), parse_level(level)?)))
+        .collect::<Result<Vec<(String, LevelFilter)>, String>>()?;
+    modules.sort_by_key(|(module, _)| std::cmp::Reverse(module.len()));
+
+    let journald = match config.format {
+        LogFormat::Journald => Some(Journald::new().map_err(|e| format!("Cannot connect to journald: {}", e))?),
+        _ => None,
+    };
+
+    let max_level = modules.iter().map(|(_, level)| *level).chain([level]).max().unwrap_or(level);
+    log::set_boxed_logger(Box::new(Logger {
+        level,
+        modules,
+        format: config.format,
+        journald,
+    })).map_err(|e| e.to_string())?;
+    log::set_max_level(max_level);
+    Ok(())
+}
+
+fn syslog_priority(level: Level) -> u8 {
+    match level {
+        Level::Error => 3,
+        Level::Warn => 4,
+        Level::Info => 6,
+        Level::Debug | Level::Trace => 7,
+    }
+}
@@ -1,11 +1,22 @@
 mod actuators;
+mod api;
+mod calendar;
+mod cli;
 mod coap;
+mod config;
+mod logging;
+mod metrics;
+mod mqtt;
+mod notify;
 mod state;
+mod timezone;
 mod web;
 
+use std::net::SocketAddr;
+use std::path::PathBuf;
 use std::sync::Arc;
 
-use clap::Parser;
+use clap::{Parser, Subcommand};
 
 #[derive(Parser)]
 #[clap(author, version, about, long_about=None)]
@@ -16,69 +27,296 @@ struct Args {
     visualcrossing_token: Option<String>,
 
     #[clap(short, long)]
-    qweather_key: String,
+    qweather_key: Option<String>,
+
+    /// JSON file with settings overriding the defaults
+    #[clap(short, long)]
+    config: Option<PathBuf>,
+    /// Address to serve the HTTP API on. The API is disabled if not set
+    #[clap(long)]
+    api_addr: Option<SocketAddr>,
+    /// Log levels, e.g. "info,home_cron::coap=debug". Overrides the configuration
+    #[clap(long)]
+    log: Option<String>,
+    /// Log output: text, json or journald
+    #[clap(long)]
+    log_format: Option<logging::LogFormat>,
+
+    #[clap(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Run the scheduling daemon. The default if no command is given
+    Run,
+    /// Discover services on the network and print the device inventory
+    Devices,
+    /// Print state read back from managed devices
+    Status {
+        /// Resources to read. All managed resources if none given
+        resources: Vec<String>,
+    },
+    /// Send a one-off command to a managed resource
+    Set {
+        resource: String,
+        /// Shades: position 0-256. LEDs: r,g,b,w. Floor heating: temperature.
+        /// AC: JSON setting, e.g. {"on":true,"mode":"cool","temperature":24}
+        payload: String,
+    },
+    /// Read the state of a managed resource
+    Get {
+        resource: String,
+    },
+    /// Print actions planned for the next days without executing them
+    Schedule {
+        #[clap(long, default_value = "2")]
+        days: u32,
+        /// Print JSON instead of a table
+        #[clap(long)]
+        json: bool,
+    },
+    /// Evaluate and print the heating/cooling state
+    State,
+    /// Print the moon phase
+    Moon,
+    /// Print the next civil twilight
+    Twilight,
+    /// Print the weather forecast
+    Forecast {
+        /// How far into the future
+        #[clap(long, default_value = "24")]
+        hours: u64,
+    },
 }
 
 #[tokio::main]
 async fn main() {
-    simple_logging::log_to_stderr(log::LevelFilter::Warn);
-
     let args = Args::parse();
+    let config = match &args.config {
+        Some(path) => config::Config::load(path).expect("Invalid configuration"),
+        None => config::Config::default(),
+    };
 
-    let moon = web::Moon::new(&args.qweather_key.clone());
+    let mut logging_config = config.logging.clone();
+    if let Some(spec) = &args.log {
+        logging_config.apply_spec(spec);
+    }
+    if let Some(format) = args.log_format {
+        logging_config.format = format;
+    }
+    logging::init(&logging_config).expect("Invalid logging configuration");
+    timezone::init(config.timezone);
+    calendar::init(&config.calendar).expect("Invalid calendar configuration");
 
-    async {
-        let result = moon.get_phase().await;
-        println!("Moon result: {:?}", result);
-    }.await;
+    let result = match &args.command {
+        Some(Command::Run) | None => {
+            run(&args, config).await;
+            Ok(())
+        },
+        Some(Command::Devices) => cli::devices(&config).await,
+        Some(Command::Status { resources }) => cli::status(&config, resources).await,
+        Some(Command::Set { resource, payload }) => cli::set(&config, resource, payload).await,
+        Some(Command::Get { resource }) => cli::get(&config, resource).await,
+        Some(Command::Schedule { days, json }) => cli::schedule(&args, &config, *days, *json).await,
+        Some(Command::State) => cli::state(&args, &config).await,
+        Some(Command::Moon) => cli::moon(&args).await,
+        Some(Command::Twilight) => cli::twilight().await,
+        Some(Command::Forecast { hours }) => cli::forecast(&args, *hours).await,
+    };
+
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}
 
+async fn run(args: &Args, config: config::Config) {
     let mut tasks = Vec::new();
 
-    let hvac_state = Arc::new(state::HvacState::new());
-    let hvac_state_for_processing = hvac_state.clone();
-    let hvac_state_openweathermap_token = args.openweathermap_token.clone();
-    let hvac_state_visualcrossing_token = args.visualcrossing_token.clone();
+    let mqtt = config.mqtt.as_ref().map(|mqtt_config| {
+        let (mqtt, event_loop) = mqtt::Mqtt::new(mqtt_config);
+        let mqtt = Arc::new(mqtt);
+        let mqtt_for_processing = mqtt.clone();
+        tasks.push(tokio::spawn(async move {
+            mqtt_for_processing.process(event_loop).await;
+        }));
+        mqtt
+    });
+    let notifier = Arc::new(notify::Notifier::new(config.notify, mqtt.clone()).expect("Invalid notification configuration"));
+
+    let devices = actuators::devices(&config.ac);
+    let moon = args.qweather_key.as_ref().map(|key| web::Moon::new(key, notifier.clone()));
+    let twilight = Arc::new(web::Twilight::new(notifier.clone()));
+    let weather = Arc::new(web::Weather::new(args.openweathermap_token.clone(), args.visualcrossing_token.clone(), notifier.clone()));
 
+    if let Some(moon) = &moon {
+        let result = moon.get_phase().await;
+        log::info!("Moon result: {:?}", result);
+    }
+
+    let inventory = Arc::new(coap::Inventory::new(config.inventory.clone()));
+    if let Err(e) = inventory.refresh().await {
+        log::warn!("Error discovering services: {}", e);
+    }
+    let configured = devices.iter().map(|d| d.1.as_str()).chain([coap::Weather::RSRC]);
+    for rsrc in inventory.missing(configured).await {
+        log::warn!(resource = rsrc; "Configured resource not found on the network. Is it a typo?");
+    }
+    tasks.push(tokio::spawn(async move {
+        inventory.process().await;
+    }));
+
+    let hvac_state = Arc::new(state::HvacState::new(config.blend));
+    let presence = Arc::new(state::Presence::new(config.presence, notifier.clone()));
+    let occupancy = Arc::new(state::Occupancy::new(config.occupancy, twilight.clone(), presence.clone()));
+    let overrides = Arc::new(state::Overrides::new(config.overrides, notifier.clone()));
+    let schedules = Arc::new(state::Schedules::new());
+    let cp = actuators::cron_processor::CronProcessor::new(overrides.clone(), notifier.clone(), schedules.clone());
+
+    let health = Arc::new(state::Health::new(config.health, notifier.clone()));
+    let health_for_processing = health.clone();
+    let health_devices = devices.clone();
+    tasks.push(tokio::spawn(async move {
+        health_for_processing.process(health_devices).await;
+    }));
+    let hvac_state_for_processing = hvac_state.clone();
+    let weather_for_hvac_state = weather.clone();
     tasks.push(tokio::spawn(async move {
-        let result = hvac_state_for_processing.process(hvac_state_openweathermap_token, hvac_state_visualcrossing_token).await;
+        let result = hvac_state_for_processing.process(weather_for_hvac_state).await;
         result.unwrap(); // TODO: Any better error handling?
     }));
 
+    let presence_for_processing = presence.clone();
+    let twilight_for_presence = twilight.clone();
+    tasks.push(tokio::spawn(async move {
+        presence_for_processing.process(twilight_for_presence).await;
+    }));
+
+    // Occupancy of all rooms follows presence, so every schedule is built again
+    let schedules_for_presence = schedules.clone();
+    let mut presence_changes = presence.subscribe();
+    tasks.push(tokio::spawn(async move {
+        while presence_changes.changed().await.is_ok() {
+            schedules_for_presence.refresh();
+        }
+    }));
+
+    let notifier_for_transitions = notifier.clone();
+    let transitions = hvac_state.subscribe_transitions();
+    tasks.push(tokio::spawn(async move {
+        notifier_for_transitions.process_transitions(transitions).await;
+    }));
+
+    let observer = Arc::new(coap::Observer::new());
+    let hvac_state_for_observations = hvac_state.clone();
+    let observations = observer.subscribe();
+    tasks.push(tokio::spawn(async move {
+        hvac_state_for_observations.process_observations(observations).await;
+    }));
+
+    let overrides_for_observations = overrides.clone();
+    let observations = observer.subscribe();
+    tasks.push(tokio::spawn(async move {
+        overrides_for_observations.process(observations).await;
+    }));
+
+    // Actuators are polled for manual changes if they are not observed
+    let mut observed = Vec::new();
+    if config.observe.sensors {
+        observed.push((coap::Weather::RSRC.to_string(), coap::Weather::PATH.to_string(), false));
+    }
+    if config.observe.actuators {
+        observed.extend(devices.iter().map(|d| (d.1.clone(), d.1.clone(), true)));
+    } else {
+        let overrides_for_polling = overrides.clone();
+        let polled = devices.iter().map(|d| d.1.clone()).collect();
+        tasks.push(tokio::spawn(async move {
+            overrides_for_polling.poll(polled).await;
+        }));
+    }
+    for (rsrc, path, actuator) in observed {
+        let observer = observer.clone();
+        let overrides_for_polling = overrides.clone();
+        tasks.push(tokio::spawn(async move {
+            observer.observe(&rsrc, &path).await;
+            if actuator {
+                log::info!(resource = rsrc.as_str(); "Polling for manual changes instead of observing");
+                overrides_for_polling.poll(vec![rsrc]).await;
+            }
+        }));
+    }
+
+    let hvac_state_for_transitions = hvac_state.clone();
+    let cp_for_transitions = cp.clone();
+    let transition_hooks = config.transitions;
+    tasks.push(tokio::spawn(async move {
+        let hooks = actuators::TransitionHooks::new(cp_for_transitions, hvac_state_for_transitions, transition_hooks);
+        hooks.process().await;
+    }));
+
     let hvac_state_for_shades = hvac_state.clone();
-    let shades_openweathermap_token = args.openweathermap_token.clone();
-    let shades_visualcrossing_token = args.visualcrossing_token.clone();
+    let cp_for_shades = cp.clone();
+    let twilight_for_shades = twilight.clone();
+    let shades_config = config.shades;
     tasks.push(tokio::spawn(async move {
-        let weather = Arc::new(web::Weather::new(shades_openweathermap_token, shades_visualcrossing_token));
-        let shades = actuators::Shades::new(hvac_state_for_shades, weather);
+        let shades = actuators::Shades::new(cp_for_shades, hvac_state_for_shades, weather, twilight_for_shades, shades_config);
         shades.process().await;
     }));
 
     let hvac_state_for_floor_heating = hvac_state.clone();
+    let cp_for_floor_heating = cp.clone();
+    let floor_heating_config = config.floor_heating;
+    let occupancy_for_floor_heating = occupancy.clone();
     tasks.push(tokio::spawn(async move {
-        let floor_heating = actuators::FloorHeating::new(hvac_state_for_floor_heating);
+        let floor_heating = actuators::FloorHeating::new(cp_for_floor_heating, hvac_state_for_floor_heating, floor_heating_config, occupancy_for_floor_heating);
         floor_heating.process().await;
     }));
 
     let hvac_state_for_ac = hvac_state.clone();
+    let cp_for_ac = cp.clone();
+    let ac_config = config.ac;
+    let twilight_for_ac = twilight.clone();
+    let occupancy_for_ac = occupancy.clone();
     tasks.push(tokio::spawn(async move {
-        let ac = actuators::Ac::new(hvac_state_for_ac);
+        let ac = actuators::Ac::new(cp_for_ac, hvac_state_for_ac, ac_config, twilight_for_ac, occupancy_for_ac);
         ac.process().await;
     }));
 
-    tasks.push(tokio::spawn(async move {
-        let leds = actuators::Leds::new(Arc::new(moon));
-        leds.process().await;
-    }));
+    if let Some(mqtt) = mqtt {
+        if let Some(home_assistant_config) = config.home_assistant {
+            let home_assistant = mqtt::HomeAssistant::new(home_assistant_config, mqtt.clone(), overrides.clone(), devices.clone());
+            tasks.push(tokio::spawn(async move {
+                home_assistant.process().await;
+            }));
+        }
 
-    /*
-    tasks.push(tokio::spawn(async move {
-        use chrono::{DateTime, TimeZone, NaiveDateTime, Utc};
-        use std::time::Duration;
-        let weather = web::Weather::new(&args.openweathermap_token);
-        let forecast = weather.get_forecast(&Duration::from_secs(3600*6)).await;
-        println!("Forecast: {:?}", forecast);
-    }));
-    */
+        let bridge = mqtt::Bridge::new(mqtt, hvac_state.clone(), overrides.clone(), schedules.clone(), presence.clone());
+        tasks.push(tokio::spawn(async move {
+            bridge.process().await;
+        }));
+    } else if config.home_assistant.is_some() {
+        log::error!("Home Assistant integration requires MQTT configuration");
+    }
+
+    if let Some(api_addr) = args.api_addr {
+        let server = Arc::new(api::Server::new(hvac_state.clone(), overrides.clone(), health.clone(), schedules.clone(), presence.clone(), devices));
+        tasks.push(tokio::spawn(async move {
+            let result = server.serve(api_addr).await;
+            result.unwrap(); // TODO: Any better error handling?
+        }));
+    }
+
+    match moon {
+        Some(moon) => {
+            let leds_config = config.leds;
+            tasks.push(tokio::spawn(async move {
+                let leds = actuators::Leds::new(cp, Arc::new(moon), twilight, leds_config, occupancy);
+                leds.process().await;
+            }));
+        },
+        None => log::warn!("Skipping LED schedule without qweather key"),
+    }
 
     for task in tasks {
         task.await.expect("Failed infinite task");
@@ -0,0 +1,126 @@
+use chrono::prelude::*;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::coap::DeviceKind;
+use crate::state::{HcState, HvacState, Schedules};
+
+/// Counters updated from all over the daemon, rendered in the Prometheus text format
+struct Registry {
+    /// Actions by resource and whether they succeeded
+    actions: BTreeMap<(String, bool), u64>,
+    action_retries: BTreeMap<String, u64>,
+    /// Number and total duration of CoAP requests by resource and method
+    coap_requests: BTreeMap<(String, &'static str), (u64, f64)>,
+    /// Web API calls by provider and whether they succeeded
+    provider_requests: BTreeMap<(String, bool), u64>,
+}
+
+static REGISTRY: Mutex<Registry> = Mutex::new(Registry {
+    actions: BTreeMap::new(),
+    action_retries: BTreeMap::new(),
+    coap_requests: BTreeMap::new(),
+    provider_requests: BTreeMap::new(),
+});
+
+pub fn action(rsrc: &str, success: bool) {
+    *REGISTRY.lock().unwrap().actions.entry((rsrc.to_string(), success)).or_default() += 1;
+}
+
+pub fn action_retry(rsrc: &str) {
+    *REGISTRY.lock().unwrap().action_retries.entry(rsrc.to_string()).or_default() += 1;
+}
+
+pub fn coap_request(rsrc: &str, method: &'static str, duration: Duration) {
+    let mut registry = REGISTRY.lock().unwrap();
+    let entry = registry.coap_requests.entry((rsrc.to_string(), method)).or_default();
+    entry.0 += 1;
+    entry.1 += duration.as_secs_f64();
+}
+
+pub fn provider_request(provider: &str, success: bool) {
+    *REGISTRY.lock().unwrap().provider_requests.entry((provider.to_string(), success)).or_default() += 1;
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+fn label(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+fn result(success: bool) -> &'static str {
+    if success { "success" } else { "failure" }
+}
+
+fn actuator(devices: &[(DeviceKind, String)], rsrc: &str) -> String {
+    devices.iter()
+        .find(|d| d.1 == rsrc)
+        .and_then(|d| serde_json::to_value(d.0).ok())
+        .and_then(|v| v.as_str().map(str::to_string))
+        .unwrap_or_default()
+}
+
+pub async fn render(hvac_state: &HvacState, schedules: &Schedules, devices: &[(DeviceKind, String)]) -> String {
+    let mut out = String::new();
+
+    header(&mut out, "home_cron_outdoor_temperature_celsius", "gauge",
+           "Outdoor temperature: current, aggregated history and aggregated forecast");
+    let decision = hvac_state.get_decisions().await.pop();
+    let temperatures = [
+        ("current", hvac_state.outdoor_temperature().await),
+        ("history", decision.as_ref().and_then(|d| d.past_value())),
+        ("forecast", decision.as_ref().and_then(|d| d.forecast_value())),
+        ("blended", decision.as_ref().map(|d| d.blended())),
+    ];
+    for (source, temperature) in temperatures {
+        if let Some(temperature) = temperature {
+            let _ = writeln!(out, "home_cron_outdoor_temperature_celsius{{source=\"{}\"}} {}", source, temperature);
+        }
+    }
+
+    header(&mut out, "home_cron_hvac_state", "gauge", "Current heating/cooling state, 1 for the active one");
+    let current = hvac_state.current_state();
+    for state in [HcState::HeatingActive, HcState::HeatingPassive, HcState::CoolingPassive, HcState::CoolingActive] {
+        let _ = writeln!(out, "home_cron_hvac_state{{state=\"{:?}\"}} {}", state, u8::from(current == Some(state)));
+    }
+
+    header(&mut out, "home_cron_next_action_seconds", "gauge", "Time until the pending action of each schedule");
+    let now = Utc::now();
+    for (schedule, planned) in schedules.upcoming() {
+        let seconds = planned.time.signed_duration_since(now).num_milliseconds().max(0) as f64 / 1000.0;
+        let _ = writeln!(out, "home_cron_next_action_seconds{{schedule=\"{}\"}} {}", label(&schedule), seconds);
+    }
+
+    let registry = REGISTRY.lock().unwrap();
+
+    header(&mut out, "home_cron_actions_total", "counter", "Actions applied to resources");
+    for ((rsrc, success), count) in &registry.actions {
+        let _ = writeln!(out, "home_cron_actions_total{{actuator=\"{}\",resource=\"{}\",result=\"{}\"}} {}",
+                         actuator(devices, rsrc), label(rsrc), result(*success), count);
+    }
+
+    header(&mut out, "home_cron_action_retries_total", "counter", "Failed attempts of actions which were retried");
+    for (rsrc, count) in &registry.action_retries {
+        let _ = writeln!(out, "home_cron_action_retries_total{{actuator=\"{}\",resource=\"{}\"}} {}",
+                         actuator(devices, rsrc), label(rsrc), count);
+    }
+
+    header(&mut out, "home_cron_coap_request_seconds", "summary", "Duration of CoAP requests including service discovery");
+    for ((rsrc, method), (count, sum)) in &registry.coap_requests {
+        let _ = writeln!(out, "home_cron_coap_request_seconds_sum{{resource=\"{}\",method=\"{}\"}} {}", label(rsrc), method, sum);
+        let _ = writeln!(out, "home_cron_coap_request_seconds_count{{resource=\"{}\",method=\"{}\"}} {}", label(rsrc), method, count);
+    }
+
+    header(&mut out, "home_cron_provider_requests_total", "counter", "Calls of web APIs");
+    for ((provider, success), count) in &registry.provider_requests {
+        let _ = writeln!(out, "home_cron_provider_requests_total{{provider=\"{}\",result=\"{}\"}} {}",
+                         label(provider), result(*success), count);
+    }
+
+    out
+}
@@ -0,0 +1,300 @@
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::mqtt::Mqtt;
+use crate::state::{HcState, HvacState, Overrides, Presence, Schedules, Signal};
+
+const COMMAND_TOPIC: &str = "command";
+
+/// Commands accepted as JSON on the command topic
+#[derive(Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+enum Command {
+    /// Keeps schedules away from the resource
+    Override { resource: String, minutes: u64 },
+    /// Pins the heating/cooling state. Automatic evaluation if null
+    Mode { mode: Option<HcState> },
+    /// Runs the pending action of a schedule now
+    Trigger { schedule: String },
+    /// Reports presence, e.g. {"command": "presence", "signal": "arriving", "source": "phone"}
+    Presence {
+        signal: Signal,
+        #[serde(default = "Command::default_source")]
+        source: String,
+    },
+}
+
+impl Command {
+    fn default_source() -> String {
+        "mqtt".to_string()
+    }
+}
+
+/// Publishes state of home_cron on retained topics and executes commands received from MQTT
+pub struct Bridge {
+    mqtt: Arc<Mqtt>,
+    hvac_state: Arc<HvacState>,
+    overrides: Arc<Overrides>,
+    schedules: Arc<Schedules>,
+    presence: Arc<Presence>,
+}
+
+impl Bridge {
+    pub fn new(mqtt: Arc<Mqtt>, hvac_state: Arc<HvacState>, overrides: Arc<Overrides>, schedules: Arc<Schedules>, presence: Arc<Presence>) -> Self {
+        Self {
+            mqtt,
+            hvac_state,
+            overrides,
+            schedules,
+            presence,
+        }
+    }
+
+    pub async fn process(&self) {
+        tokio::join!(
+            self.publish_state(),
+            self.publish_decisions(),
+            self.publish_upcoming(),
+            self.publish_results(),
+            self.publish_presence(),
+            self.process_commands(),
+        );
+    }
+
+    fn publish<T: Serialize>(&self, topic: &str, value: &T) {
+        let result = match serde_json::to_vec(value) {
+            Ok(payload) => self.mqtt.publish(topic, payload, true),
+            Err(e) => Err(e.to_string()),
+        };
+        if let Err(e) = result {
+            log::error!(topic = topic; "Error publishing MQTT topic: {}", e);
+        }
+    }
+
+    async fn publish_state(&self) {
+        let mut state = self.hvac_state.subscribe();
+        loop {
+            let current = *state.borrow_and_update();
+            if let Some(current) = current {
+                self.publish("hvac/state", &current);
+            }
+            if state.changed().await.is_err() {
+                return;
+            }
+        }
+    }
+
+    async fn publish_decisions(&self) {
+        let mut decisions = self.hvac_state.subscribe_decisions();
+        loop {
+            let decision = match decisions.recv().await {
+                Ok(decision) => decision,
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => return,
+            };
+
+            self.publish("hvac/decision", &decision);
+            self.publish("temperature/blended", &decision.blended());
+            if let Some(outdoor) = self.hvac_state.outdoor_temperature().await {
+                self.publish("temperature/outdoor", &outdoor);
+            }
+        }
+    }
+
+    async fn publish_upcoming(&self) {
+        let mut upcoming = self.schedules.subscribe_upcoming();
+        loop {
+            let current = upcoming.borrow_and_update().clone();
+            self.publish("schedule/upcoming", &current);
+            if upcoming.changed().await.is_err() {
+                return;
+            }
+        }
+    }
+
+    async fn publish_results(&self) {
+        let mut results = self.schedules.subscribe_results();
+        loop {
+            let result = match results.recv().await {
+                Ok(result) => result,
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => return,
+            };
+
+            self.publish(&format!("action/{}", result.resource), &result);
+        }
+    }
+
+    async fn publish_presence(&self) {
+        let mut presence = self.presence.subscribe();
+        loop {
+            let current = *presence.borrow_and_update();
+            self.publish("presence", &current);
+            if presence.changed().await.is_err() {
+                return;
+            }
+        }
+    }
+
+    async fn process_commands(&self) {
+        let topic = self.mqtt.topic(COMMAND_TOPIC);
+        let mut messages = self.mqtt.subscribe(&topic);
+        loop {
+            let message = match messages.recv().await {
+                Ok(message) => message,
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => return,
+            };
+            if message.topic != topic {
+                continue;
+            }
+
+            if let Err(e) = self.handle(&message.payload).await {
+                log::warn!("Error executing MQTT command: {}", e);
+            }
+        }
+    }
+
+    async fn handle(&self, payload: &[u8]) -> Result<(), String> {
+        let command = serde_json::from_slice::<Command>(payload).map_err(|e| format!("Invalid command: {}", e))?;
+        self.execute(command).await
+    }
+
+    async fn execute(&self, command: Command) -> Result<(), String> {
+        match command {
+            Command::Override { resource, minutes } => {
+                self.overrides.set(&resource, SystemTime::now() + Duration::from_secs(minutes * 60)).await;
+                Ok(())
+            },
+            Command::Mode { mode } => {
+                self.hvac_state.set_mode(mode).await;
+                Ok(())
+            },
+            Command::Trigger { schedule } => self.schedules.trigger(&schedule),
+            Command::Presence { signal, source } => {
+                self.presence.signal(signal, &source).await;
+                Ok(())
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rumqttc::{AsyncClient, Event, EventLoop, MqttOptions, Packet, Publish, QoS};
+    use serde_json::json;
+
+    use super::*;
+    use crate::mqtt::MqttConfig;
+    use crate::notify::{Notifier, NotifyConfig};
+    use crate::state::{BlendConfig, OverrideConfig, PresenceState};
+
+    fn bridge(config: serde_json::Value) -> (Bridge, EventLoop) {
+        let config: MqttConfig = serde_json::from_value(config).unwrap();
+        let (mqtt, event_loop) = Mqtt::new(&config);
+        let notifier = Arc::new(Notifier::new(NotifyConfig::default(), None).unwrap());
+        let bridge = Bridge::new(
+            Arc::new(mqtt),
+            Arc::new(HvacState::new(BlendConfig::default())),
+            Arc::new(Overrides::new(OverrideConfig::default(), notifier.clone())),
+            Arc::new(Schedules::new()),
+            Arc::new(Presence::new(None, notifier)),
+        );
+        (bridge, event_loop)
+    }
+
+    #[test]
+    fn parse_commands() {
+        let command = serde_json::from_str(r#"{"command": "override", "resource": "bac", "minutes": 30}"#);
+        assert!(matches!(command, Ok(Command::Override { resource, minutes: 30 }) if resource == "bac"));
+
+        let command = serde_json::from_str(r#"{"command": "mode", "mode": "CoolingActive"}"#);
+        assert!(matches!(command, Ok(Command::Mode { mode: Some(HcState::CoolingActive) })));
+        let command = serde_json::from_str(r#"{"command": "mode", "mode": null}"#);
+        assert!(matches!(command, Ok(Command::Mode { mode: None })));
+
+        let command = serde_json::from_str(r#"{"command": "trigger", "schedule": "leds"}"#);
+        assert!(matches!(command, Ok(Command::Trigger { schedule }) if schedule == "leds"));
+
+        let command = serde_json::from_str(r#"{"command": "presence", "signal": "arriving"}"#);
+        assert!(matches!(command, Ok(Command::Presence { signal: Signal::Arriving, source }) if source == "mqtt"));
+
+        for invalid in [r#"{"command": "reboot"}"#, r#"{"command": "override", "resource": "bac"}"#, r#"{"mode": null}"#, "mode"] {
+            assert!(serde_json::from_str::<Command>(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[tokio::test]
+    async fn execute_commands() {
+        let (bridge, _event_loop) = bridge(json!({"host": "localhost"}));
+
+        bridge.handle(br#"{"command": "override", "resource": "bac", "minutes": 30}"#).await.unwrap();
+        assert!(bridge.overrides.is_overridden("bac").await);
+        assert!(!bridge.overrides.is_overridden("dac").await);
+
+        bridge.handle(br#"{"command": "mode", "mode": "HeatingPassive"}"#).await.unwrap();
+        assert_eq!(bridge.hvac_state.current_state(), Some(HcState::HeatingPassive));
+
+        bridge.handle(br#"{"command": "presence", "signal": "left", "source": "phone"}"#).await.unwrap();
+        assert_eq!(bridge.presence.subscribe().borrow().state, PresenceState::Away);
+
+        // Nothing is planned by any schedule
+        assert!(bridge.handle(br#"{"command": "trigger", "schedule": "leds"}"#).await.is_err());
+        assert!(bridge.handle(b"not json").await.is_err());
+    }
+
+    async fn next_publish(event_loop: &mut EventLoop, topic: &str) -> Publish {
+        let receive = async {
+            loop {
+                if let Event::Incoming(Packet::Publish(publish)) = event_loop.poll().await.unwrap() {
+                    if publish.topic == topic {
+                        return publish;
+                    }
+                }
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(10), receive).await.unwrap()
+    }
+
+    /// Runs against the broker given as host:port in MQTT_TEST_BROKER, e.g. a local mosquitto
+    #[tokio::test]
+    async fn broker() {
+        let Ok(broker) = std::env::var("MQTT_TEST_BROKER") else {
+            return;
+        };
+        let (host, port) = broker.split_once(':').unwrap_or((&broker, "1883"));
+        let port: u16 = port.parse().unwrap();
+        let prefix = format!("home_cron_test_{}", rand::random::<u32>());
+
+        let (bridge, event_loop) = bridge(json!({"host": host, "port": port, "client_id": prefix, "prefix": prefix}));
+        let bridge = Arc::new(bridge);
+        let bridge_for_connection = bridge.clone();
+        tokio::spawn(async move {
+            bridge_for_connection.mqtt.process(event_loop).await;
+        });
+        let bridge_for_processing = bridge.clone();
+        tokio::spawn(async move {
+            bridge_for_processing.process().await;
+        });
+        bridge.hvac_state.set_mode(Some(HcState::CoolingActive)).await;
+        tokio::time::sleep(Duration::from_secs(1)).await;
+
+        // State is retained for clients connecting later
+        let (client, mut event_loop) = AsyncClient::new(MqttOptions::new(format!("{}_client", prefix), host, port), 16);
+        let state_topic = format!("{}/hvac/state", prefix);
+        client.subscribe(&state_topic, QoS::AtLeastOnce).await.unwrap();
+        let state = next_publish(&mut event_loop, &state_topic).await;
+        assert!(state.retain);
+        assert_eq!(state.payload.as_ref(), br#""CoolingActive""#);
+
+        client.publish(format!("{}/command", prefix), QoS::AtLeastOnce, false, r#"{"command": "mode", "mode": "HeatingActive"}"#).await.unwrap();
+        let state = next_publish(&mut event_loop, &state_topic).await;
+        assert_eq!(state.payload.as_ref(), br#""HeatingActive""#);
+
+        // The retained state of the test is not left on the broker
+        client.publish(&state_topic, QoS::AtLeastOnce, true, "").await.unwrap();
+        next_publish(&mut event_loop, &state_topic).await;
+    }
+}
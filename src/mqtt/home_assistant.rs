@@ -0,0 +1,312 @@
+use rust_decimal::prelude::*;
+use serde::Deserialize;
+use serde_json::json;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::coap::{basic, AcCommand, AcStatus, DeviceKind, DeviceReport, DeviceStatus, FloorHeatingCommand, LedCommand, ShadeCommand};
+use crate::mqtt::Mqtt;
+use crate::state::Overrides;
+
+/// Home Assistant climate modes of the AC and the mode characters understood by the units
+const AC_MODES: [(&str, char); 5] = [("auto", 'a'), ("cool", 'c'), ("dry", 'd'), ("fan_only", 'f'), ("heat", 'h')];
+const AC_FAN_MODES: [(&str, char); 5] = [("auto", 'a'), ("quiet", 'q'), ("low", 'l'), ("medium", 'm'), ("high", 'h')];
+const SHADE_CLOSED: u16 = 256;
+const LED_MAX: u16 = 255;
+
+#[derive(Clone, Deserialize)]
+#[serde(default)]
+pub struct HomeAssistantConfig {
+    pub discovery_prefix: String,
+    /// How often the state of devices is read and published
+    pub state_seconds: u64,
+}
+
+impl Default for HomeAssistantConfig {
+    fn default() -> Self {
+        Self {
+            discovery_prefix: "homeassistant".to_string(),
+            state_seconds: 60,
+        }
+    }
+}
+
+/// Exposes managed devices and the heating/cooling state as Home Assistant entities
+/// and relays commands from Home Assistant to the devices
+pub struct HomeAssistant {
+    config: HomeAssistantConfig,
+    mqtt: Arc<Mqtt>,
+    overrides: Arc<Overrides>,
+    devices: Vec<(DeviceKind, String)>,
+}
+
+impl HomeAssistant {
+    pub fn new(config: HomeAssistantConfig, mqtt: Arc<Mqtt>, overrides: Arc<Overrides>, devices: Vec<(DeviceKind, String)>) -> Self {
+        Self {
+            config,
+            mqtt,
+            overrides,
+            devices,
+        }
+    }
+
+    pub async fn process(&self) {
+        tokio::join!(
+            self.process_discovery(),
+            self.process_states(),
+            self.process_commands(),
+        );
+    }
+
+    fn device_topic(&self, rsrc: &str, topic: &str) -> String {
+        self.mqtt.topic(&format!("device/{}/{}", rsrc, topic))
+    }
+
+    /// Publishes discovery configs now and each time Home Assistant comes online
+    async fn process_discovery(&self) {
+        let birth_topic = format!("{}/status", self.config.discovery_prefix);
+        let mut messages = self.mqtt.subscribe(&birth_topic);
+
+        self.publish_discovery().await;
+        loop {
+            let message = match messages.recv().await {
+                Ok(message) => message,
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => return,
+            };
+
+            if message.topic == birth_topic && message.payload.as_ref() == b"online" {
+                self.publish_discovery().await;
+            }
+        }
+    }
+
+    async fn publish_discovery(&self) {
+        let mut entities: Vec<_> = self.devices.iter()
+            .map(|(kind, rsrc)| self.entity(*kind, rsrc))
+            .collect();
+        entities.push(self.hvac_state_entity());
+
+        for (component, object_id, mut config) in entities {
+            config["unique_id"] = json!(format!("{}_{}", self.mqtt.prefix(), object_id));
+            config["object_id"] = json!(object_id);
+            config["availability_topic"] = json!(self.mqtt.topic("status"));
+            config["device"] = json!({
+                "identifiers": [self.mqtt.prefix()],
+                "name": "home_cron",
+            });
+
+            let topic = format!("{}/{}/{}/{}/config",
+                                self.config.discovery_prefix, component, self.mqtt.prefix(), object_id);
+            let result = self.mqtt.publish_absolute(&topic, config.to_string(), true);
+            if let Err(e) = result {
+                log::error!(entity = object_id; "Error publishing Home Assistant discovery: {}", e);
+            }
+        }
+    }
+
+    fn entity(&self, kind: DeviceKind, rsrc: &str) -> (&'static str, String, serde_json::Value) {
+        let state_topic = self.device_topic(rsrc, "state");
+        match kind {
+            DeviceKind::Ac => ("climate", rsrc.to_string(), json!({
+                "name": format!("AC {}", rsrc),
+                "modes": ["off", "auto", "cool", "dry", "fan_only", "heat"],
+                "mode_command_topic": self.device_topic(rsrc, "mode"),
+                "mode_state_topic": state_topic,
+                "mode_state_template": "{% if not value_json.on %}off{% else %}{{ {'a': 'auto', 'c': 'cool', 'd': 'dry', 'f': 'fan_only', 'h': 'heat'}[value_json.mode] }}{% endif %}",
+                "fan_modes": AC_FAN_MODES.iter().map(|m| m.0).collect::<Vec<_>>(),
+                "fan_mode_command_topic": self.device_topic(rsrc, "fan"),
+                "fan_mode_state_topic": state_topic,
+                "fan_mode_state_template": "{{ {'a': 'auto', 'q': 'quiet', 'l': 'low', 'm': 'medium', 'h': 'high'}[value_json.fan] }}",
+                "temperature_command_topic": self.device_topic(rsrc, "temperature"),
+                "temperature_state_topic": state_topic,
+                "temperature_state_template": "{{ value_json.temperature }}",
+                "min_temp": 16,
+                "max_temp": 31,
+                "temp_step": 1,
+            })),
+            DeviceKind::FloorHeating => ("climate", rsrc.to_string(), json!({
+                "name": format!("Floor heating {}", rsrc),
+                "modes": ["heat"],
+                "temperature_command_topic": self.device_topic(rsrc, "temperature"),
+                "temperature_state_topic": state_topic,
+                "temperature_state_template": "{{ value_json.setpoint }}",
+                "current_temperature_topic": state_topic,
+                "current_temperature_template": "{{ value_json.temperature }}",
+                "min_temp": 5,
+                "max_temp": 35,
+                "temp_step": 0.5,
+            })),
+            DeviceKind::Leds => ("light", rsrc.to_string(), json!({
+                "name": format!("LED {}", rsrc),
+                "command_topic": self.device_topic(rsrc, "set"),
+                "state_topic": state_topic,
+                "state_value_template": "{{ 'ON' if value_json.r + value_json.g + value_json.b + value_json.w > 0 else 'OFF' }}",
+                "rgbw_command_topic": self.device_topic(rsrc, "rgbw"),
+                "rgbw_state_topic": state_topic,
+                "rgbw_value_template": "{{ value_json.r }},{{ value_json.g }},{{ value_json.b }},{{ value_json.w }}",
+                "on_command_type": "last",
+            })),
+            DeviceKind::Shades => ("cover", rsrc.to_string(), json!({
+                "name": format!("Shade {}", rsrc),
+                "device_class": "shade",
+                "command_topic": self.device_topic(rsrc, "set"),
+                "payload_stop": null,
+                "position_topic": state_topic,
+                "position_template": format!("{{{{ 100 - (value_json.position * 100 / {}) | round(0) | int }}}}", SHADE_CLOSED),
+                "set_position_topic": self.device_topic(rsrc, "position"),
+            })),
+        }
+    }
+
+    fn hvac_state_entity(&self) -> (&'static str, String, serde_json::Value) {
+        ("sensor", "hvac_state".to_string(), json!({
+            "name": "HVAC state",
+            "device_class": "enum",
+            "options": ["HeatingActive", "HeatingPassive", "CoolingPassive", "CoolingActive"],
+            "state_topic": self.mqtt.topic("hvac/state"),
+            "value_template": "{{ value_json }}",
+        }))
+    }
+
+    async fn process_states(&self) {
+        loop {
+            for report in DeviceReport::read_all(&self.devices).await {
+                if let Some(status) = report.status {
+                    self.publish_state(&report.resource, &status).await;
+                }
+            }
+
+            tokio::time::sleep(Duration::from_secs(self.config.state_seconds)).await;
+        }
+    }
+
+    async fn publish_state(&self, rsrc: &str, status: &DeviceStatus) {
+        let payload = match status {
+            DeviceStatus::Ac(status) => serde_json::to_string(status),
+            DeviceStatus::FloorHeating(status) => serde_json::to_string(status),
+            DeviceStatus::Leds(status) => serde_json::to_string(status),
+            DeviceStatus::Shades(status) => serde_json::to_string(status),
+        };
+        let result = match payload {
+            Ok(payload) => self.mqtt.publish(&format!("device/{}/state", rsrc), payload, true),
+            Err(e) => Err(e.to_string()),
+        };
+        if let Err(e) = result {
+            log::error!(resource = rsrc; "Error publishing state: {}", e);
+        }
+    }
+
+    async fn process_commands(&self) {
+        let mut messages = self.mqtt.subscribe(&self.device_topic("+", "+"));
+        let device_prefix = self.mqtt.topic("device/");
+        loop {
+            let message = match messages.recv().await {
+                Ok(message) => message,
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => return,
+            };
+
+            let (rsrc, field) = match message.topic.strip_prefix(&device_prefix).and_then(|t| t.split_once('/')) {
+                Some(command) => command,
+                None => continue,
+            };
+            let kind = match self.devices.iter().find(|d| d.1 == rsrc) {
+                Some(device) => device.0,
+                None => continue,
+            };
+            if field == "state" {
+                continue;
+            }
+
+            let payload = String::from_utf8_lossy(&message.payload);
+            log::info!(resource = rsrc; "Home Assistant command: {} {}", field, payload);
+            let result = self.execute(kind, rsrc, field, payload.trim()).await;
+            match result {
+                Ok(()) => {
+                    // Changes from Home Assistant are manual, schedules should not revert them right away
+                    self.overrides.manual(rsrc).await;
+                    match DeviceStatus::read(kind, rsrc).await {
+                        Ok(status) => self.publish_state(rsrc, &status).await,
+                        Err(e) => log::warn!(resource = rsrc; "Error reading state: {}", e),
+                    }
+                },
+                Err(e) => log::warn!(resource = rsrc; "Error executing Home Assistant command: {}", e),
+            }
+        }
+    }
+
+    async fn execute(&self, kind: DeviceKind, rsrc: &str, field: &str, payload: &str) -> Result<(), String> {
+        match (kind, field) {
+            (DeviceKind::Ac, _) => {
+                let current = match DeviceStatus::read(kind, rsrc).await? {
+                    DeviceStatus::Ac(status) => status,
+                    _ => return Err(format!("{} is not an AC", rsrc)),
+                };
+                let target = Self::ac_target(current, field, payload)?;
+                let temperature = target.temperature.round().to_u8().ok_or(format!("Invalid temperature {}", target.temperature))?;
+                basic::set_actuator(rsrc, &AcCommand::new(target.on, target.mode, target.fan, temperature)?).await
+            },
+            (DeviceKind::FloorHeating, "temperature") => {
+                let setpoint = Decimal::from_str(payload).map_err(|e| e.to_string())?;
+                basic::set_actuator(rsrc, &FloorHeatingCommand::new(setpoint)?).await
+            },
+            (DeviceKind::Leds, "set") => {
+                let target = match payload {
+                    // Keep the current color if already on, otherwise turn on white
+                    "ON" => match DeviceStatus::read(kind, rsrc).await? {
+                        DeviceStatus::Leds(s) if [s.r, s.g, s.b, s.w].iter().any(|c| *c > 0) => return Ok(()),
+                        _ => LedCommand { r: 0, g: 0, b: 0, w: LED_MAX },
+                    },
+                    "OFF" => LedCommand { r: 0, g: 0, b: 0, w: 0 },
+                    _ => return Err(format!("Unknown light command {}", payload)),
+                };
+                basic::set_actuator(rsrc, &target).await
+            },
+            (DeviceKind::Leds, "rgbw") => {
+                let values = payload.split(',')
+                    .map(|v| v.trim().parse::<u16>().map(|v| v.min(LED_MAX)).map_err(|e| e.to_string()))
+                    .collect::<Result<Vec<u16>, String>>()?;
+                match values[..] {
+                    [r, g, b, w] => basic::set_actuator(rsrc, &LedCommand { r, g, b, w }).await,
+                    _ => Err(format!("Expected four RGBW values, got {}", payload)),
+                }
+            },
+            (DeviceKind::Shades, "set") => {
+                let position = match payload {
+                    "OPEN" => 0,
+                    "CLOSE" => SHADE_CLOSED,
+                    _ => return Err(format!("Unknown cover command {}", payload)),
+                };
+                basic::set_actuator(rsrc, &ShadeCommand::new(position)?).await
+            },
+            (DeviceKind::Shades, "position") => {
+                // Home Assistant counts the open percentage, shades count how far they are closed
+                let open = payload.parse::<u16>().map_err(|e| e.to_string())?.min(100);
+                basic::set_actuator(rsrc, &ShadeCommand::new((100 - open) * SHADE_CLOSED / 100)?).await
+            },
+            _ => Err(format!("Unknown command topic {}", field)),
+        }
+    }
+
+    fn ac_target(mut target: AcStatus, field: &str, payload: &str) -> Result<AcStatus, String> {
+        match field {
+            "mode" if payload == "off" => target.on = false,
+            "mode" => {
+                target.mode = AC_MODES.iter().find(|m| m.0 == payload)
+                    .ok_or(format!("Unknown AC mode {}", payload))?.1;
+                target.on = true;
+            },
+            "fan" => {
+                target.fan = AC_FAN_MODES.iter().find(|m| m.0 == payload)
+                    .ok_or(format!("Unknown AC fan mode {}", payload))?.1;
+            },
+            "temperature" => {
+                target.temperature = Decimal::from_str(payload).map_err(|e| e.to_string())?;
+            },
+            _ => return Err(format!("Unknown AC command topic {}", field)),
+        }
+        Ok(target)
+    }
+}
@@ -0,0 +1,126 @@
+mod bridge;
+mod home_assistant;
+
+use rumqttc::{AsyncClient, Event, EventLoop, LastWill, MqttOptions, Packet, Publish, QoS};
+use serde::Deserialize;
+use std::time::Duration;
+use tokio::sync::broadcast;
+
+pub use bridge::Bridge;
+pub use home_assistant::{HomeAssistant, HomeAssistantConfig};
+
+const STATUS_TOPIC: &str = "status";
+
+#[derive(Clone, Deserialize)]
+pub struct MqttConfig {
+    pub host: String,
+    #[serde(default = "MqttConfig::default_port")]
+    pub port: u16,
+    #[serde(default = "MqttConfig::default_client_id")]
+    pub client_id: String,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// Prepended to all topics
+    #[serde(default = "MqttConfig::default_prefix")]
+    pub prefix: String,
+}
+
+impl MqttConfig {
+    fn default_port() -> u16 {
+        1883
+    }
+
+    fn default_client_id() -> String {
+        "home_cron".to_string()
+    }
+
+    fn default_prefix() -> String {
+        "home_cron".to_string()
+    }
+}
+
+/// Connection to an MQTT broker
+pub struct Mqtt {
+    client: AsyncClient,
+    prefix: String,
+    subscriptions: std::sync::Mutex<Vec<String>>,
+    incoming: broadcast::Sender<Publish>,
+}
+
+impl Mqtt {
+    pub fn new(config: &MqttConfig) -> (Self, EventLoop) {
+        let mut options = MqttOptions::new(&config.client_id, &config.host, config.port);
+        options.set_keep_alive(Duration::from_secs(30));
+        if let Some(username) = &config.username {
+            options.set_credentials(username, config.password.clone().unwrap_or_default());
+        }
+        options.set_last_will(LastWill::new(format!("{}/{}", config.prefix, STATUS_TOPIC),
+                                            "offline", QoS::AtLeastOnce, true));
+
+        let (client, event_loop) = AsyncClient::new(options, 64);
+        (Self {
+            client,
+            prefix: config.prefix.clone(),
+            subscriptions: std::sync::Mutex::new(Vec::new()),
+            incoming: broadcast::channel(16).0,
+        }, event_loop)
+    }
+
+    pub fn prefix(&self) -> &str {
+        &self.prefix
+    }
+
+    pub fn topic(&self, topic: &str) -> String {
+        format!("{}/{}", self.prefix, topic)
+    }
+
+    /// Publishes `payload` on `topic` under the configured prefix
+    pub fn publish(&self, topic: &str, payload: impl Into<Vec<u8>>, retain: bool) -> Result<(), String> {
+        self.publish_absolute(&self.topic(topic), payload, retain)
+    }
+
+    /// Publishes `payload` on `topic` without the prefix. Fails instead of waiting if the
+    /// outgoing queue is full, e.g. while the broker is unreachable
+    pub fn publish_absolute(&self, topic: &str, payload: impl Into<Vec<u8>>, retain: bool) -> Result<(), String> {
+        self.client.try_publish(topic, QoS::AtLeastOnce, retain, payload)
+            .map_err(|e| e.to_string())
+    }
+
+    /// Receiver of messages published on the absolute `topic`. The subscription
+    /// is renewed on every reconnection.
+    pub fn subscribe(&self, topic: &str) -> broadcast::Receiver<Publish> {
+        self.subscriptions.lock().unwrap().push(topic.to_string());
+        if let Err(e) = self.client.try_subscribe(topic, QoS::AtLeastOnce) {
+            log::error!(topic = topic; "Error subscribing to MQTT topic: {}", e);
+        }
+        self.incoming.subscribe()
+    }
+
+    /// Drives the connection. Must be running for anything to be published or received
+    pub async fn process(&self, mut event_loop: EventLoop) {
+        loop {
+            match event_loop.poll().await {
+                Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                    log::info!("Connected to MQTT broker");
+                    let subscriptions = self.subscriptions.lock().unwrap().clone();
+                    for topic in subscriptions {
+                        if let Err(e) = self.client.try_subscribe(&topic, QoS::AtLeastOnce) {
+                            log::error!(topic = topic; "Error subscribing to MQTT topic: {}", e);
+                        }
+                    }
+                    if let Err(e) = self.client.try_publish(self.topic(STATUS_TOPIC), QoS::AtLeastOnce, true, "online") {
+                        log::error!("Error publishing MQTT status: {}", e);
+                    }
+                },
+                Ok(Event::Incoming(Packet::Publish(publish))) => {
+                    let _ = self.incoming.send(publish);
+                },
+                Ok(_) => (),
+                Err(e) => {
+                    log::warn!("MQTT connection error: {}", e);
+                    tokio::time::sleep(Duration::from_secs(5)).await;
+                },
+            }
+        }
+    }
+}
@@ -0,0 +1,14 @@
+use futures::future::BoxFuture;
+
+use crate::notify::{Event, Sink};
+
+pub struct Log();
+
+impl Sink for Log {
+    fn send<'a>(&'a self, event: &'a Event) -> BoxFuture<'a, Result<(), String>> {
+        Box::pin(async move {
+            log::warn!(kind:? = event.kind; "{}: {}", event.title, event.message);
+            Ok(())
+        })
+    }
+}
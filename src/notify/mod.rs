@@ -0,0 +1,210 @@
+mod log;
+mod mqtt;
+mod ntfy;
+mod smtp;
+mod webhook;
+
+use futures::future::BoxFuture;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::sync::Arc;
+use tokio::sync::{broadcast, Mutex};
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::metrics;
+use crate::mqtt::Mqtt;
+use crate::state::Transition;
+use log::Log;
+use mqtt::MqttTopic;
+use ntfy::Ntfy;
+use smtp::Smtp;
+use webhook::Webhook;
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EventKind {
+    ActionFailure,
+    Arrival,
+    Departure,
+    DeviceOffline,
+    DeviceOnline,
+    OverrideExpired,
+    ProviderOutage,
+    ProviderRecovered,
+    Transition,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Event {
+    pub kind: EventKind,
+    pub title: String,
+    pub message: String,
+}
+
+impl Event {
+    pub fn new(kind: EventKind, title: String, message: String) -> Self {
+        Self {
+            kind,
+            title,
+            message,
+        }
+    }
+}
+
+/// Destination of notifications
+pub trait Sink: Send + Sync {
+    fn send<'a>(&'a self, event: &'a Event) -> BoxFuture<'a, Result<(), String>>;
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SinkConfig {
+    /// POSTs the event as JSON
+    Webhook { url: String },
+    /// Pushes the event to an ntfy-style topic URL
+    Ntfy {
+        url: String,
+        priority: Option<String>,
+    },
+    Smtp {
+        #[serde(default = "SinkConfig::default_smtp_server")]
+        server: String,
+        from: String,
+        to: Vec<String>,
+    },
+    /// Publishes the event as JSON. Requires the `mqtt` section of the configuration
+    Mqtt {
+        #[serde(default = "SinkConfig::default_mqtt_topic")]
+        topic: String,
+    },
+    /// Prints the event
+    Log,
+}
+
+impl SinkConfig {
+    fn default_smtp_server() -> String {
+        "localhost:25".to_string()
+    }
+
+    fn default_mqtt_topic() -> String {
+        "events".to_string()
+    }
+}
+
+#[derive(Clone, Deserialize)]
+pub struct RuleConfig {
+    /// Kinds of events passed to the sink. All of them if empty
+    #[serde(default)]
+    pub events: Vec<EventKind>,
+    #[serde(flatten)]
+    pub sink: SinkConfig,
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(default)]
+pub struct NotifyConfig {
+    pub sinks: Vec<RuleConfig>,
+}
+
+impl Default for NotifyConfig {
+    fn default() -> Self {
+        Self {
+            sinks: vec![RuleConfig {
+                events: Vec::new(),
+                sink: SinkConfig::Log,
+            }],
+        }
+    }
+}
+
+struct Rule {
+    events: Vec<EventKind>,
+    sink: Box<dyn Sink>,
+}
+
+pub struct Notifier {
+    rules: Vec<Rule>,
+    outages: Mutex<BTreeSet<String>>,
+}
+
+impl Notifier {
+    pub fn new(config: NotifyConfig, mqtt: Option<Arc<Mqtt>>) -> Result<Self, String> {
+        let rules = config.sinks.into_iter()
+            .map(|rule| {
+                let sink: Box<dyn Sink> = match rule.sink {
+                    SinkConfig::Webhook { url } => Box::new(Webhook::new(&url)),
+                    SinkConfig::Ntfy { url, priority } => Box::new(Ntfy::new(&url, priority)),
+                    SinkConfig::Smtp { server, from, to } => Box::new(Smtp::new(&server, &from, &to)),
+                    SinkConfig::Mqtt { topic } => Box::new(MqttTopic::new(
+                        mqtt.clone().ok_or("MQTT notification sink requires MQTT configuration")?, &topic)),
+                    SinkConfig::Log => Box::new(Log()),
+                };
+                Ok(Rule {
+                    events: rule.events,
+                    sink,
+                })
+            })
+            .collect::<Result<Vec<Rule>, String>>()?;
+
+        Ok(Self {
+            rules,
+            outages: Mutex::new(BTreeSet::new()),
+        })
+    }
+
+    pub async fn notify(&self, event: Event) {
+        for rule in &self.rules {
+            if !rule.events.is_empty() && !rule.events.contains(&event.kind) {
+                continue;
+            }
+
+            if let Err(e) = rule.sink.send(&event).await {
+                ::log::error!("Error sending notification: {}", e);
+            }
+        }
+    }
+
+    /// Notifies when a web provider starts failing and when it recovers, not on every request
+    pub async fn provider_result<T>(&self, provider: &str, result: &Result<T, String>) {
+        metrics::provider_request(provider, result.is_ok());
+
+        let event = {
+            let mut outages = self.outages.lock().await;
+            match result {
+                Err(e) if outages.insert(provider.to_string()) =>
+                    Some(Event::new(EventKind::ProviderOutage,
+                                    format!("{} outage", provider),
+                                    format!("Request to {} failed: {}", provider, e))),
+                Ok(_) if outages.remove(provider) =>
+                    Some(Event::new(EventKind::ProviderRecovered,
+                                    format!("{} recovered", provider),
+                                    format!("{} responds again", provider))),
+                _ => None,
+            }
+        };
+
+        if let Some(event) = event {
+            self.notify(event).await;
+        }
+    }
+
+    pub async fn process_transitions(&self, mut transitions: broadcast::Receiver<Transition>) {
+        loop {
+            let transition = match transitions.recv().await {
+                Ok(transition) => transition,
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => return,
+            };
+
+            if let Some(from) = transition.from {
+                let message = match transition.temperature {
+                    Some(temperature) => format!("HVAC state changed from {:?} to {:?} at {} degrees", from, transition.to, temperature),
+                    None => format!("HVAC state changed from {:?} to {:?}", from, transition.to),
+                };
+                self.notify(Event::new(EventKind::Transition,
+                                       format!("HVAC state changed to {:?}", transition.to),
+                                       message)).await;
+            }
+        }
+    }
+}
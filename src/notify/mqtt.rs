@@ -0,0 +1,29 @@
+use futures::future::BoxFuture;
+use std::sync::Arc;
+
+use crate::mqtt::Mqtt;
+use crate::notify::{Event, Sink};
+
+/// Publishes events as JSON on a topic of the MQTT connection
+pub struct MqttTopic {
+    mqtt: Arc<Mqtt>,
+    topic: String,
+}
+
+impl MqttTopic {
+    pub fn new(mqtt: Arc<Mqtt>, topic: &str) -> Self {
+        Self {
+            mqtt,
+            topic: topic.to_string(),
+        }
+    }
+}
+
+impl Sink for MqttTopic {
+    fn send<'a>(&'a self, event: &'a Event) -> BoxFuture<'a, Result<(), String>> {
+        Box::pin(async move {
+            let payload = serde_json::to_vec(event).map_err(|e| e.to_string())?;
+            self.mqtt.publish(&self.topic, payload, false)
+        })
+    }
+}
@@ -0,0 +1,39 @@
+use futures::future::BoxFuture;
+
+use crate::notify::{Event, Sink};
+
+/// Push notifications through an ntfy-style server: the message is the body, the title a header
+pub struct Ntfy {
+    url: String,
+    priority: Option<String>,
+}
+
+impl Ntfy {
+    pub fn new(url: &str, priority: Option<String>) -> Self {
+        Self {
+            url: url.to_string(),
+            priority,
+        }
+    }
+}
+
+impl Sink for Ntfy {
+    fn send<'a>(&'a self, event: &'a Event) -> BoxFuture<'a, Result<(), String>> {
+        Box::pin(async move {
+            let mut request = reqwest::Client::new().post(&self.url)
+                .header("Title", &event.title)
+                .header("Tags", serde_json::to_value(event.kind).map_err(|e| e.to_string())?
+                    .as_str().unwrap_or_default())
+                .body(event.message.clone());
+            if let Some(priority) = &self.priority {
+                request = request.header("Priority", priority);
+            }
+
+            request.send().await
+                .map_err(|e| e.to_string())?
+                .error_for_status()
+                .map_err(|e| e.to_string())?;
+            Ok(())
+        })
+    }
+}
@@ -0,0 +1,78 @@
+use futures::future::BoxFuture;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+
+use crate::notify::{Event, Sink};
+
+/// Minimal client of a local SMTP relay, without authentication or TLS
+pub struct Smtp {
+    server: String,
+    from: String,
+    to: Vec<String>,
+}
+
+impl Smtp {
+    pub fn new(server: &str, from: &str, to: &[String]) -> Self {
+        Self {
+            server: server.to_string(),
+            from: from.to_string(),
+            to: to.to_vec(),
+        }
+    }
+
+    pub async fn send(&self, subject: &str, body: &str) -> Result<(), String> {
+        let stream = TcpStream::connect(&self.server).await.map_err(|e| e.to_string())?;
+        let (reader, mut writer) = stream.into_split();
+        let mut reader = BufReader::new(reader);
+
+        Self::expect(&mut reader, 220).await?;
+        Self::command(&mut reader, &mut writer, "HELO home_cron", 250).await?;
+        Self::command(&mut reader, &mut writer, &format!("MAIL FROM:<{}>", self.from), 250).await?;
+        for to in &self.to {
+            Self::command(&mut reader, &mut writer, &format!("RCPT TO:<{}>", to), 250).await?;
+        }
+        Self::command(&mut reader, &mut writer, "DATA", 354).await?;
+
+        // Lines starting with a dot would end the message early
+        let body = body.lines()
+            .map(|l| if l.starts_with('.') { format!(".{}", l) } else { l.to_string() })
+            .collect::<Vec<_>>()
+            .join("\r\n");
+        let message = format!("From: {}\r\nTo: {}\r\nSubject: {}\r\n\r\n{}\r\n.",
+                              self.from, self.to.join(", "), subject, body);
+        Self::command(&mut reader, &mut writer, &message, 250).await?;
+        Self::command(&mut reader, &mut writer, "QUIT", 221).await
+    }
+
+    async fn command<R, W>(reader: &mut R, writer: &mut W, line: &str, code: u16) -> Result<(), String>
+        where R: AsyncBufReadExt + Unpin,
+              W: AsyncWriteExt + Unpin,
+    {
+        writer.write_all(format!("{}\r\n", line).as_bytes()).await.map_err(|e| e.to_string())?;
+        Self::expect(reader, code).await
+    }
+
+    async fn expect<R: AsyncBufReadExt + Unpin>(reader: &mut R, code: u16) -> Result<(), String> {
+        loop {
+            let mut line = String::new();
+            if reader.read_line(&mut line).await.map_err(|e| e.to_string())? == 0 {
+                return Err("SMTP connection closed".to_string());
+            }
+
+            // "250-" continues a multiline reply, "250 " ends it
+            if line.len() >= 4 && line.as_bytes()[3] == b'-' {
+                continue;
+            }
+            return match line.get(..3).and_then(|c| c.parse::<u16>().ok()) {
+                Some(c) if c == code => Ok(()),
+                _ => Err(format!("Unexpected SMTP reply: {}", line.trim_end())),
+            };
+        }
+    }
+}
+
+impl Sink for Smtp {
+    fn send<'a>(&'a self, event: &'a Event) -> BoxFuture<'a, Result<(), String>> {
+        Box::pin(self.send(&event.title, &event.message))
+    }
+}
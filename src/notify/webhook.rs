@@ -0,0 +1,28 @@
+use futures::future::BoxFuture;
+
+use crate::notify::{Event, Sink};
+
+/// POSTs events as JSON
+pub struct Webhook {
+    url: String,
+}
+
+impl Webhook {
+    pub fn new(url: &str) -> Self {
+        Self {
+            url: url.to_string(),
+        }
+    }
+}
+
+impl Sink for Webhook {
+    fn send<'a>(&'a self, event: &'a Event) -> BoxFuture<'a, Result<(), String>> {
+        Box::pin(async move {
+            reqwest::Client::new().post(&self.url).json(event).send().await
+                .map_err(|e| e.to_string())?
+                .error_for_status()
+                .map_err(|e| e.to_string())?;
+            Ok(())
+        })
+    }
+}
@@ -0,0 +1,141 @@
+use chrono::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::coap::{self, basic, DeviceKind};
+use crate::notify::{Event, EventKind, Notifier};
+use crate::timezone;
+
+#[derive(Clone, Deserialize)]
+#[serde(default)]
+pub struct HealthConfig {
+    pub probe_minutes: u64,
+    /// Consecutive failed probes after which a device is considered offline
+    pub offline_after: u32,
+}
+
+impl Default for HealthConfig {
+    fn default() -> Self {
+        Self {
+            probe_minutes: 5,
+            offline_after: 3,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HealthStatus {
+    Online,
+    Degraded,
+    Offline,
+}
+
+#[derive(Clone, Serialize)]
+pub struct DeviceHealth {
+    pub status: HealthStatus,
+    pub since: DateTime<Utc>,
+    pub failures: u32,
+    /// Time of the first of the consecutive failed probes
+    pub failing_since: Option<DateTime<Utc>>,
+    pub last_error: Option<String>,
+}
+
+/// Liveness of the managed devices tracked with periodic probes, by path of the probed endpoint
+pub struct Health {
+    config: HealthConfig,
+    notifier: Arc<Notifier>,
+    devices: tokio::sync::Mutex<BTreeMap<String, DeviceHealth>>,
+}
+
+impl Health {
+    pub fn new(config: HealthConfig, notifier: Arc<Notifier>) -> Self {
+        Self {
+            config,
+            notifier,
+            devices: tokio::sync::Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    pub async fn get_all(&self) -> BTreeMap<String, DeviceHealth> {
+        self.devices.lock().await.clone()
+    }
+
+    async fn record(&self, path: &str, result: Result<(), String>) {
+        let now = Utc::now();
+        let mut devices = self.devices.lock().await;
+        let prev = devices.get(path).cloned();
+        let failures = match (&result, &prev) {
+            (Ok(_), _) => 0,
+            (Err(_), Some(prev)) => prev.failures + 1,
+            (Err(_), None) => 1,
+        };
+        let status = if failures == 0 {
+            HealthStatus::Online
+        } else if failures >= self.config.offline_after {
+            HealthStatus::Offline
+        } else {
+            HealthStatus::Degraded
+        };
+
+        let prev_status = prev.as_ref().map(|p| p.status);
+        let since = match &prev {
+            Some(prev) if prev.status == status => prev.since,
+            _ => now,
+        };
+        let failing_since = match &prev {
+            Some(prev) if failures > 1 => prev.failing_since,
+            _ if failures > 0 => Some(now),
+            _ => None,
+        };
+        let last_error = result.err().or(prev.and_then(|p| p.last_error));
+        devices.insert(path.to_string(), DeviceHealth {
+            status,
+            since,
+            failures,
+            failing_since,
+            last_error: last_error.clone(),
+        });
+        drop(devices);
+
+        if status == HealthStatus::Offline && prev_status != Some(HealthStatus::Offline) {
+            self.notifier.notify(Event::new(
+                    EventKind::DeviceOffline,
+                    format!("{} is offline", path),
+                    format!("{} has not answered {} probes since {}: {}", path, failures,
+                            timezone::from_system(failing_since.unwrap_or(now).into()), last_error.unwrap_or_default()),
+            )).await;
+        } else if status == HealthStatus::Online && prev_status == Some(HealthStatus::Offline) {
+            self.notifier.notify(Event::new(
+                    EventKind::DeviceOnline,
+                    format!("{} is back online", path),
+                    format!("{} answers again", path),
+            )).await;
+        }
+    }
+
+    async fn probe(kind: Option<DeviceKind>, rsrc: &str) -> Result<(), String> {
+        match kind {
+            Some(_) => basic::get_actuator(rsrc).await.map(|_| ()),
+            None => coap::Weather::new().get_temperature().await.map(|_| ()),
+        }
+    }
+
+    /// Probes all `devices` and the outdoor temperature sensor periodically. The sensor is
+    /// served by an actuator, so it is tracked by its own path
+    pub async fn process(&self, devices: Vec<(DeviceKind, String)>) {
+        let mut probed: Vec<_> = devices.into_iter().map(|(kind, rsrc)| (Some(kind), rsrc.clone(), rsrc)).collect();
+        probed.push((None, coap::Weather::RSRC.to_string(), coap::Weather::PATH.to_string()));
+
+        loop {
+            for (kind, rsrc, path) in &probed {
+                let result = Self::probe(*kind, rsrc).await;
+                self.record(path, result).await;
+            }
+
+            tokio::time::sleep(Duration::from_secs(self.config.probe_minutes * 60)).await;
+        }
+    }
+}
@@ -1,11 +1,17 @@
 use chrono::prelude::*;
 use rust_decimal::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::sync::Arc;
 use std::time::{Duration, SystemTime};
 
 use crate::coap;
 use crate::web;
 
-#[derive(Copy, Clone)]
+const MAX_DECISIONS: usize = 72;
+const OBSERVED_TEMP_VALIDITY: Duration = Duration::from_secs(3600);
+
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize, Serialize)]
 pub enum HcState {
     HeatingActive,
     HeatingPassive,
@@ -13,146 +19,298 @@ pub enum HcState {
     CoolingActive,
 }
 
+#[derive(Copy, Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Aggregate {
+    Mean,
+    Min,
+    Max,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct BlendConfig {
+    /// Number of hourly samples of the outdoor temperature history taken into account
+    pub past_hours: usize,
+    /// How far into the future the temperature forecast reaches
+    pub forecast_hours: u64,
+    pub past_weight: Decimal,
+    pub forecast_weight: Decimal,
+    /// Samples further from now weigh less, halving every this many hours.
+    /// Ignored by min and max aggregates.
+    pub decay_half_life_hours: Option<f64>,
+    pub aggregate: Aggregate,
+}
+
+impl Default for BlendConfig {
+    fn default() -> Self {
+        Self {
+            past_hours: 72,
+            forecast_hours: 24,
+            past_weight: Decimal::ONE,
+            forecast_weight: Decimal::ONE,
+            decay_half_life_hours: None,
+            aggregate: Aggregate::Mean,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Transition {
+    pub from: Option<HcState>,
+    pub to: HcState,
+    /// Blended outdoor temperature which triggered the transition. None if the state was
+    /// pinned before any temperature was known
+    pub temperature: Option<Decimal>,
+}
+
+/// Inputs and outcome of a single evaluation of the heating/cooling state
+#[derive(Clone, Serialize)]
+pub struct Decision {
+    time: DateTime<Utc>,
+    past: Vec<Decimal>,
+    forecast: Vec<Decimal>,
+    past_value: Option<Decimal>,
+    forecast_value: Option<Decimal>,
+    blended: Decimal,
+    previous_state: Option<HcState>,
+    state: HcState,
+    /// Set if the state was forced instead of derived from the temperature
+    pinned: bool,
+    /// Weights and aggregate the temperatures were blended with
+    config: BlendConfig,
+}
+
+impl Decision {
+    pub fn blended(&self) -> Decimal {
+        self.blended
+    }
+
+    pub fn past_value(&self) -> Option<Decimal> {
+        self.past_value
+    }
+
+    pub fn forecast_value(&self) -> Option<Decimal> {
+        self.forecast_value
+    }
+}
+
+struct Blend {
+    past: Vec<Decimal>,
+    forecast: Vec<Decimal>,
+    past_value: Option<Decimal>,
+    forecast_value: Option<Decimal>,
+    value: Option<Decimal>,
+}
+
 pub struct HvacState {
+    config: BlendConfig,
     ext_temp_history: tokio::sync::Mutex<Vec<Decimal>>,
-    ext_temp_forecast: tokio::sync::Mutex<Option<Decimal>>,
-    state: tokio::sync::Mutex<Option<HcState>>,
+    ext_temp_forecast: tokio::sync::Mutex<Option<Vec<Decimal>>>,
+    ext_temp_observed: tokio::sync::Mutex<Option<(Decimal, SystemTime)>>,
+    state: tokio::sync::watch::Sender<Option<HcState>>,
+    transitions: tokio::sync::broadcast::Sender<Transition>,
+    decisions: tokio::sync::Mutex<VecDeque<Decision>>,
+    decisions_tx: tokio::sync::broadcast::Sender<Decision>,
+    pinned: tokio::sync::Mutex<Option<HcState>>,
 }
 
 impl HvacState {
-    pub fn new() -> Self {
+    pub fn new(config: BlendConfig) -> Self {
         HvacState {
-            ext_temp_history: tokio::sync::Mutex::new(Vec::with_capacity(72)),
+            ext_temp_history: tokio::sync::Mutex::new(Vec::with_capacity(config.past_hours)),
             ext_temp_forecast: tokio::sync::Mutex::new(None),
-            state: tokio::sync::Mutex::new(None),
+            ext_temp_observed: tokio::sync::Mutex::new(None),
+            state: tokio::sync::watch::channel(None).0,
+            transitions: tokio::sync::broadcast::channel(16).0,
+            decisions: tokio::sync::Mutex::new(VecDeque::with_capacity(MAX_DECISIONS)),
+            decisions_tx: tokio::sync::broadcast::channel(4).0,
+            pinned: tokio::sync::Mutex::new(None),
+            config,
         }
     }
 
     pub async fn get_state(&self) -> HcState {
+        let mut state = self.subscribe();
         loop {
-            let state = *self.state.lock().await;
-            if state.is_some() {
-                return state.unwrap();
+            if let Some(state) = *state.borrow_and_update() {
+                return state;
             }
-            tokio::time::sleep(Duration::from_secs(5)).await;
+            // The sender lives as long as self, so it cannot be dropped while waiting
+            let _ = state.changed().await;
+        }
+    }
+
+    /// State already evaluated, without waiting for it if it is not known yet
+    pub fn current_state(&self) -> Option<HcState> {
+        *self.state.borrow()
+    }
+
+    /// Receiver notified each time the heating/cooling state changes
+    pub fn subscribe(&self) -> tokio::sync::watch::Receiver<Option<HcState>> {
+        self.state.subscribe()
+    }
+
+    /// Receiver of every heating/cooling state change together with the temperature causing it
+    pub fn subscribe_transitions(&self) -> tokio::sync::broadcast::Receiver<Transition> {
+        self.transitions.subscribe()
+    }
+
+    /// Receiver of every evaluation of the heating/cooling state
+    pub fn subscribe_decisions(&self) -> tokio::sync::broadcast::Receiver<Decision> {
+        self.decisions_tx.subscribe()
+    }
+
+    /// Forces the state regardless of the temperature, or returns to automatic evaluation if None
+    pub async fn set_mode(&self, mode: Option<HcState>) {
+        log::info!("Heating/cooling state pinned to {:?}", mode);
+        let mut pinned = self.pinned.lock().await;
+        *pinned = mode;
+        match mode {
+            Some(state) => self.apply_state(state, self.blend().await.value),
+            None => {
+                drop(pinned);
+                self.update_state().await;
+            },
+        }
+    }
+
+    /// Latest known outdoor temperature
+    pub async fn outdoor_temperature(&self) -> Option<Decimal> {
+        match self.observed_temperature().await {
+            Some(temperature) => Some(temperature),
+            None => self.ext_temp_history.lock().await.last().cloned(),
+        }
+    }
+
+    /// Sets the state and announces the transition if it changed
+    fn apply_state(&self, state: HcState, temperature: Option<Decimal>) {
+        let prev_state = *self.state.borrow();
+        self.state.send_if_modified(|s| {
+            let modified = *s != Some(state);
+            *s = Some(state);
+            modified
+        });
+        if prev_state != Some(state) {
+            log::info!("Changing state from {:?} to {:?} at {:?}", prev_state, state, temperature);
+            let _ = self.transitions.send(Transition {
+                from: prev_state,
+                to: state,
+                temperature,
+            });
         }
     }
 
     async fn update_state(&self) {
-        let avg = self.average().await;
-        println!("Avg: {}", avg);
-        let prev_state = *self.state.lock().await;
+        // Held until the state is set, so a concurrent set_mode is not overwritten
+        let pinned_lock = self.pinned.lock().await;
+        let pinned = *pinned_lock;
+        let blend = self.blend().await;
+        let avg = match blend.value {
+            Some(avg) => avg,
+            None => {
+                log::warn!("No temperature data to evaluate heating/cooling state");
+                return;
+            },
+        };
+        log::info!("Avg: {}", avg);
+        let prev_state = *self.state.borrow();
+        let state = pinned.unwrap_or_else(|| Self::next_state(prev_state, avg));
+        self.apply_state(state, Some(avg));
+        drop(pinned_lock);
+
+        let mut decisions = self.decisions.lock().await;
+        if decisions.len() >= MAX_DECISIONS {
+            decisions.pop_front();
+        }
+        let decision = Decision {
+            time: Utc::now(),
+            past: blend.past,
+            forecast: blend.forecast,
+            past_value: blend.past_value,
+            forecast_value: blend.forecast_value,
+            blended: avg,
+            previous_state: prev_state,
+            state,
+            pinned: pinned.is_some(),
+            config: self.config.clone(),
+        };
+        decisions.push_back(decision.clone());
+        let _ = self.decisions_tx.send(decision);
+    }
+
+    pub async fn get_decisions(&self) -> Vec<Decision> {
+        self.decisions.lock().await.iter().cloned().collect()
+    }
 
-        *self.state.lock().await = match prev_state {
+    fn next_state(prev_state: Option<HcState>, avg: Decimal) -> HcState {
+        match prev_state {
             None => {
                 if avg > Decimal::new(18, 0) {
-                    Some(HcState::CoolingActive)
+                    HcState::CoolingActive
                 } else if avg > Decimal::new(13, 0) {
-                    Some(HcState::CoolingPassive)
+                    HcState::CoolingPassive
                 } else if avg > Decimal::new(11, 0) {
-                    Some(HcState::HeatingPassive)
+                    HcState::HeatingPassive
                 } else {
-                    Some(HcState::HeatingActive)
+                    HcState::HeatingActive
                 }
             },
             Some(HcState::HeatingActive) => {
                 if avg > Decimal::new(20, 0) {
-                    Some(HcState::CoolingActive)
+                    HcState::CoolingActive
                 } else if avg > Decimal::new(15, 0) {
-                    Some(HcState::CoolingPassive)
+                    HcState::CoolingPassive
                 } else if avg > Decimal::new(13, 0) {
-                    Some(HcState::HeatingPassive)
+                    HcState::HeatingPassive
                 } else {
-                    Some(HcState::HeatingActive)
+                    HcState::HeatingActive
                 }
             },
             Some(HcState::HeatingPassive) => {
                 if avg > Decimal::new(20, 0) {
-                    Some(HcState::CoolingActive)
+                    HcState::CoolingActive
                 } else if avg > Decimal::new(15, 0) {
-                    Some(HcState::CoolingPassive)
+                    HcState::CoolingPassive
                 } else if avg > Decimal::new(11, 0) {
-                    Some(HcState::HeatingPassive)
+                    HcState::HeatingPassive
                 } else {
-                    Some(HcState::HeatingActive)
+                    HcState::HeatingActive
                 }
             },
             Some(HcState::CoolingPassive) => {
                 if avg > Decimal::new(20, 0) {
-                    Some(HcState::CoolingActive)
+                    HcState::CoolingActive
                 } else if avg > Decimal::new(13, 0) {
-                    Some(HcState::CoolingPassive)
+                    HcState::CoolingPassive
                 } else if avg > Decimal::new(11, 0) {
-                    Some(HcState::HeatingPassive)
+                    HcState::HeatingPassive
                 } else {
-                    Some(HcState::HeatingActive)
+                    HcState::HeatingActive
                 }
             },
             Some(HcState::CoolingActive) => {
                 if avg > Decimal::new(18, 0) {
-                    Some(HcState::CoolingActive)
+                    HcState::CoolingActive
                 } else if avg > Decimal::new(13, 0) {
-                    Some(HcState::CoolingPassive)
+                    HcState::CoolingPassive
                 } else if avg > Decimal::new(11, 0) {
-                    Some(HcState::HeatingPassive)
+                    HcState::HeatingPassive
                 } else {
-                    Some(HcState::HeatingActive)
+                    HcState::HeatingActive
                 }
             },
         }
     }
 
-    pub async fn process(&self, openweather_token: Option<String>, visualcrossing_token: Option<String>) -> Result<(), String> {
-        println!("Starting processing hvac state");
-        let weather = web::Weather::new(openweather_token, visualcrossing_token);
+    pub async fn process(&self, weather: Arc<web::Weather>) -> Result<(), String> {
+        log::info!("Starting processing hvac state");
 
-        let now = Utc::now();
-        let start_time = now - chrono::Duration::hours(72);
-        println!("Getting temperature for range 72 hours ago until now");
-        let temps = weather.get_temperature_history(start_time, now).await.unwrap();
-        for temp in &temps {
-            println!("Temp: {:?}", temp);
-        }
-        self.ext_temp_history.lock().await.extend_from_slice(&temps);
-	
+        self.load_history(&weather).await?;
         let mut last_measurement_time = Utc::now() - chrono::Duration::hours(1);
 
         loop {
-            // TODO: Some retries, trying other sources?
-            let curr_val = coap::Weather::new().get_temperature().await;
-            if let Ok(curr_val) = curr_val {
-                async {
-                    let mut temp_history = self.ext_temp_history.lock().await;
-                    temp_history.remove(0);
-                    temp_history.push(curr_val);
-                    println!("Temp: {:?}", curr_val);
-                }.await;
-            } else {
-                // Could not get temperature. Copy last one as fallback solution
-                async {
-                    let mut temp_history = self.ext_temp_history.lock().await;
-                    let last = temp_history.last().cloned();
-                    if let Some(last) = last {
-                        temp_history.remove(0);
-                        temp_history.push(last);
-                        println!("Guessing temp: {:?}", last);
-                    }
-                }.await;
-            }
-
-            println!("Getting temperature forecast");
-            let forecast = weather.get_forecast(&Duration::from_secs(24 * 3600)).await;
-            async {
-                let mut temp_forecast = self.ext_temp_forecast.lock().await;
-                if let Ok(forecast) = forecast {
-                    *temp_forecast = Some(forecast.get_temperature());
-                    println!("Temp: {:?}", *temp_forecast);
-                } else {
-                    *temp_forecast = None;
-                }
-            }.await;
-
-            self.update_state().await;
+            self.refresh(&weather).await;
 
             // Wait one more hour
             last_measurement_time = last_measurement_time + chrono::Duration::hours(1);
@@ -163,29 +321,220 @@ impl HvacState {
         }
     }
 
-    async fn past_average(&self) -> Decimal {
-        let vec = self.ext_temp_history.lock().await;
-        let mut sum = Decimal::new(0, 0);
-        for val in vec.iter() {
-            sum += val;
+    /// Fills the outdoor temperature history from the weather service
+    pub async fn load_history(&self, weather: &web::Weather) -> Result<(), String> {
+        let now = Utc::now();
+        let past_hours = self.config.past_hours;
+        let start_time = now - chrono::Duration::hours(past_hours.try_into().map_err(|e: std::num::TryFromIntError| e.to_string())?);
+        log::debug!("Getting temperature for range {} hours ago until now", past_hours);
+        let temps = weather.get_temperature_history(start_time, now).await?;
+        for temp in &temps {
+            log::debug!("Temp: {:?}", temp);
+        }
+        let skip = temps.len().saturating_sub(past_hours);
+        self.ext_temp_history.lock().await.extend_from_slice(&temps[skip..]);
+        Ok(())
+    }
+
+    /// Takes the current temperature and forecast into account and evaluates the state again
+    pub async fn refresh(&self, weather: &web::Weather) {
+        // TODO: Some retries, trying other sources?
+        let curr_val = match self.observed_temperature().await {
+            Some(val) => Ok(val),
+            None => coap::Weather::new().get_temperature().await,
+        };
+        if let Ok(curr_val) = curr_val {
+            async {
+                self.push_history(curr_val).await;
+                log::info!("Temp: {:?}", curr_val);
+            }.await;
+        } else {
+            // Could not get temperature. Copy last one as fallback solution
+            async {
+                let last = self.ext_temp_history.lock().await.last().cloned();
+                if let Some(last) = last {
+                    self.push_history(last).await;
+                    log::warn!("Guessing temp: {:?}", last);
+                }
+            }.await;
+        }
+
+        log::debug!("Getting temperature forecast");
+        let forecast = weather.get_forecast(&Duration::from_secs(self.config.forecast_hours * 3600)).await;
+        async {
+            let mut temp_forecast = self.ext_temp_forecast.lock().await;
+            if let Ok(forecast) = forecast {
+                *temp_forecast = Some(forecast.get_temperatures().to_vec());
+                log::info!("Forecast temp: {:?}", forecast.get_temperature());
+            } else {
+                *temp_forecast = None;
+            }
+        }.await;
+
+        self.update_state().await;
+    }
+
+    /// Keeps the outdoor temperature pushed by the sensor so it does not need to be polled
+    pub async fn process_observations(&self, mut notifications: tokio::sync::broadcast::Receiver<coap::Notification>) {
+        use tokio::sync::broadcast::error::RecvError;
+
+        loop {
+            let notification = match notifications.recv().await {
+                Ok(notification) => notification,
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => return,
+            };
+            if notification.path != coap::Weather::PATH {
+                continue;
+            }
+
+            match coap::Weather::parse_temperature(&notification.content) {
+                Ok(temp) => *self.ext_temp_observed.lock().await = Some((temp, SystemTime::now())),
+                Err(e) => log::warn!("Invalid observed temperature: {}", e),
+            }
         }
-        let avg = sum / Decimal::new(vec.len().try_into().unwrap(), 0);
+    }
 
-        avg
+    async fn observed_temperature(&self) -> Option<Decimal> {
+        let observed = *self.ext_temp_observed.lock().await;
+        observed
+            .filter(|(_, time)| time.elapsed().is_ok_and(|age| age < OBSERVED_TEMP_VALIDITY))
+            .map(|(temp, _)| temp)
     }
 
-    pub async fn average(&self) -> Decimal {
-        let past_avg = self.past_average().await;
-        let forecast = self.ext_temp_forecast.lock().await;
+    async fn push_history(&self, temp: Decimal) {
+        let mut temp_history = self.ext_temp_history.lock().await;
+        temp_history.push(temp);
+        let excess = temp_history.len().saturating_sub(self.config.past_hours);
+        temp_history.drain(..excess);
+    }
 
-        if let Some(future_avg) = *forecast {
-            let sum = past_avg + future_avg;
-            let avg = sum / Decimal::new(2, 0);
+    fn decay_weight(&self, age_hours: f64) -> Decimal {
+        match self.config.decay_half_life_hours {
+            Some(half_life) if half_life > 0.0 =>
+                Decimal::from_f64(0.5_f64.powf(age_hours / half_life)).unwrap_or(Decimal::ZERO),
+            _ => Decimal::ONE,
+        }
+    }
 
-            avg
-        } else {
-            past_avg
+    fn aggregate(&self, samples: &[Decimal], ages: impl Iterator<Item = f64>) -> Option<Decimal> {
+        match self.config.aggregate {
+            Aggregate::Min => samples.iter().min().cloned(),
+            Aggregate::Max => samples.iter().max().cloned(),
+            Aggregate::Mean => {
+                let mut sum = Decimal::ZERO;
+                let mut weights = Decimal::ZERO;
+                for (val, age) in samples.iter().zip(ages) {
+                    let weight = self.decay_weight(age);
+                    sum += val * weight;
+                    weights += weight;
+                }
+
+                if weights.is_zero() { None } else { Some(sum / weights) }
+            },
+        }
+    }
+
+    async fn blend(&self) -> Blend {
+        let past = self.ext_temp_history.lock().await.clone();
+        let forecast = self.ext_temp_forecast.lock().await.clone().unwrap_or_default();
+
+        // History is hourly with the newest sample last, forecast comes in 3-hour slots
+        let past_value = self.aggregate(&past, (0..past.len()).rev().map(|i| i as f64));
+        let forecast_value = self.aggregate(&forecast, (0..forecast.len()).map(|i| i as f64 * 3.0 + 1.5));
+
+        let value = match (past_value, forecast_value) {
+            (Some(past_value), Some(forecast_value)) => {
+                let weights = self.config.past_weight + self.config.forecast_weight;
+                if weights.is_zero() {
+                    None
+                } else {
+                    Some((past_value * self.config.past_weight + forecast_value * self.config.forecast_weight) / weights)
+                }
+            },
+            (Some(past_value), None) => Some(past_value),
+            (None, forecast_value) => forecast_value,
+        };
+
+        Blend {
+            past,
+            forecast,
+            past_value,
+            forecast_value,
+            value,
         }
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use rust_decimal_macros::dec;
+
+    use super::*;
+
+    async fn hvac_state(config: BlendConfig, past: &[Decimal], forecast: Option<&[Decimal]>) -> HvacState {
+        let hvac_state = HvacState::new(config);
+        for temp in past {
+            hvac_state.push_history(*temp).await;
+        }
+        *hvac_state.ext_temp_forecast.lock().await = forecast.map(|forecast| forecast.to_vec());
+        hvac_state
+    }
+
+    #[tokio::test]
+    async fn history_limited_to_past_hours() {
+        let config = BlendConfig { past_hours: 2, ..Default::default() };
+        let state = hvac_state(config, &[dec!(1), dec!(2), dec!(3)], None).await;
+        assert_eq!(*state.ext_temp_history.lock().await, vec![dec!(2), dec!(3)]);
+
+        let config = BlendConfig { past_hours: 0, ..Default::default() };
+        let state = hvac_state(config, &[dec!(1), dec!(2)], None).await;
+        assert!(state.ext_temp_history.lock().await.is_empty());
+        assert_eq!(state.blend().await.value, None);
+    }
+
+    #[tokio::test]
+    async fn weighted_average() {
+        let config = BlendConfig { past_weight: dec!(1), forecast_weight: dec!(3), ..Default::default() };
+        let blend = hvac_state(config, &[dec!(10), dec!(20)], Some(&[dec!(18), dec!(22)])).await.blend().await;
+        assert_eq!(blend.past_value, Some(dec!(15)));
+        assert_eq!(blend.forecast_value, Some(dec!(20)));
+        assert_eq!(blend.value, Some(dec!(18.75)));
+    }
+
+    #[tokio::test]
+    async fn single_source() {
+        let blend = hvac_state(BlendConfig::default(), &[dec!(10)], None).await.blend().await;
+        assert_eq!(blend.value, Some(dec!(10)));
+
+        let blend = hvac_state(BlendConfig::default(), &[], Some(&[dec!(12)])).await.blend().await;
+        assert_eq!(blend.value, Some(dec!(12)));
+
+        let config = BlendConfig { past_weight: dec!(0), forecast_weight: dec!(0), ..Default::default() };
+        let blend = hvac_state(config, &[dec!(10)], Some(&[dec!(12)])).await.blend().await;
+        assert_eq!(blend.value, None);
+    }
+
+    #[tokio::test]
+    async fn min_and_max() {
+        for (aggregate, past, forecast) in [(Aggregate::Min, dec!(-5), dec!(3)), (Aggregate::Max, dec!(7), dec!(9))] {
+            let config = BlendConfig { aggregate, ..Default::default() };
+            let blend = hvac_state(config, &[dec!(7), dec!(-5), dec!(0)], Some(&[dec!(3), dec!(9)])).await.blend().await;
+            assert_eq!(blend.past_value, Some(past));
+            assert_eq!(blend.forecast_value, Some(forecast));
+        }
+    }
+
+    #[tokio::test]
+    async fn decay() {
+        // The newest sample weighs 1, the one an hour older 0.5
+        let config = BlendConfig { decay_half_life_hours: Some(1.0), ..Default::default() };
+        let blend = hvac_state(config, &[dec!(0), dec!(30)], None).await.blend().await;
+        assert_eq!(blend.past_value, Some(dec!(20)));
+
+        // Decay does not apply to min and max
+        let config = BlendConfig { decay_half_life_hours: Some(1.0), aggregate: Aggregate::Max, ..Default::default() };
+        let blend = hvac_state(config, &[dec!(30), dec!(0)], None).await.blend().await;
+        assert_eq!(blend.past_value, Some(dec!(30)));
+    }
+}
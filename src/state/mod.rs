@@ -1,3 +1,13 @@
+mod health;
 mod hvac;
+mod occupancy;
+mod overrides;
+mod presence;
+mod schedules;
 
-pub use hvac::{HvacState, HcState};
+pub use health::{Health, HealthConfig};
+pub use hvac::{BlendConfig, HvacState, HcState, Transition};
+pub use occupancy::{Occupancy, OccupancyConfig};
+pub use overrides::{OverrideConfig, Overrides};
+pub use presence::{Presence, PresenceConfig, PresenceState, Signal};
+pub use schedules::Schedules;
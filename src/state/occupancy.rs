@@ -0,0 +1,138 @@
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use crate::actuators::Trigger;
+use crate::state::{Presence, PresenceState};
+use crate::web;
+
+/// Time a room is in use, e.g. from "22:00" to "07:00"
+#[derive(Clone, Deserialize)]
+pub struct Period {
+    pub from: Trigger,
+    pub to: Trigger,
+}
+
+#[derive(Clone, Deserialize)]
+pub struct Room {
+    /// Resources serving the room
+    pub resources: Vec<String>,
+    pub periods: Vec<Period>,
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(default)]
+pub struct OccupancyConfig {
+    pub rooms: BTreeMap<String, Room>,
+}
+
+impl Default for OccupancyConfig {
+    fn default() -> Self {
+        let period = |from: &str, to: &str| Period {
+            from: from.parse().unwrap(),
+            to: to.parse().unwrap(),
+        };
+        let room = |resources: &[&str], period: Period| Room {
+            resources: resources.iter().map(|r| r.to_string()).collect(),
+            periods: vec![period],
+        };
+
+        Self {
+            rooms: BTreeMap::from([
+                ("bathrooms".to_string(), room(&["gbrfh", "mbrfh"], period("07:00", "23:00"))),
+                ("bedroom".to_string(), room(&["bac"], period("22:00", "07:00"))),
+                ("kitchen".to_string(), room(&["kfh"], period("07:00", "23:00"))),
+                ("living_room".to_string(), room(&["dac", "lac"], period("07:00", "22:00"))),
+                ("office".to_string(), room(&["oac"], period("09:00 on workdays", "17:00 on workdays"))),
+            ]),
+        }
+    }
+}
+
+/// Change of occupancy of a room
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Change {
+    pub time: SystemTime,
+    pub occupied: bool,
+}
+
+/// Rooms in use according to their profiles, unless nobody is home
+pub struct Occupancy {
+    rooms: BTreeMap<String, Room>,
+    twilight: Arc<web::Twilight>,
+    presence: Arc<Presence>,
+}
+
+impl Occupancy {
+    pub fn new(config: OccupancyConfig, twilight: Arc<web::Twilight>, presence: Arc<Presence>) -> Self {
+        Self {
+            rooms: config.rooms,
+            twilight,
+            presence,
+        }
+    }
+
+    /// Name and profile of the room served by `rsrc`
+    pub fn room(&self, rsrc: &str) -> Option<(&str, &Room)> {
+        self.rooms.iter()
+            .find(|(_, room)| room.resources.iter().any(|r| r == rsrc))
+            .map(|(name, room)| (name.as_str(), room))
+    }
+
+    /// Next start and end of every period of the room served by `rsrc`, in order.
+    /// While nobody is home rooms are only left. When presence changes, rooms are restored
+    /// to their profiles or vacated at the time of the change, which schedules built again
+    /// after it run right away.
+    pub async fn changes(&self, rsrc: &str, after: SystemTime) -> Vec<Change> {
+        let Some((name, room)) = self.room(rsrc) else { return Vec::new() };
+        let presence = self.presence.status();
+        let away = presence.state == PresenceState::Away;
+
+        let mut changes = Vec::new();
+        if let Some(since) = presence.since {
+            let time = SystemTime::from(since);
+            if time > after {
+                let occupied = !away && self.scheduled(room, time).await;
+                if away || occupied {
+                    changes.push(Change { time, occupied });
+                }
+            }
+        }
+
+        for period in &room.periods {
+            for (trigger, occupied) in [(&period.from, true), (&period.to, false)] {
+                if away && occupied {
+                    continue;
+                }
+                match trigger.next_after(after, &self.twilight).await {
+                    Ok(time) => changes.push(Change { time, occupied }),
+                    Err(e) => log::warn!(room = name; "Skipping occupancy change at {}: {}", trigger, e),
+                }
+            }
+        }
+        changes.sort_by_key(|c| c.time);
+        changes
+    }
+
+    /// Whether the room served by `rsrc` is in use at `at`. None if the resource has no room
+    pub async fn is_occupied(&self, rsrc: &str, at: SystemTime) -> Option<bool> {
+        let (_, room) = self.room(rsrc)?;
+        Some(!self.presence.is_away() && self.scheduled(room, at).await)
+    }
+
+    /// Whether the profile of the room has it in use at `at`
+    async fn scheduled(&self, room: &Room, at: SystemTime) -> bool {
+        for period in &room.periods {
+            // Within a period its end comes before its next start
+            let from = period.from.next_after(at, &self.twilight).await;
+            let to = period.to.next_after(at, &self.twilight).await;
+            if let (Ok(from), Ok(to)) = (from, to) {
+                if to < from {
+                    return true;
+                }
+            }
+        }
+        false
+    }
+}
@@ -0,0 +1,176 @@
+use chrono::prelude::*;
+use ciborium::value::Value;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::coap::{basic, CborParser, Notification};
+use crate::notify::{Event, EventKind, Notifier};
+use crate::timezone;
+
+#[derive(Clone, Deserialize)]
+#[serde(default)]
+pub struct OverrideConfig {
+    /// Detect changes made outside of home_cron
+    pub enabled: bool,
+    /// How long schedules leave a manually changed resource alone
+    pub duration_minutes: u64,
+    /// Time after a command during which the resource may still be reaching the target
+    pub settle_seconds: u64,
+    /// Interval of reading back actuators which are not observed
+    pub poll_minutes: u64,
+}
+
+impl Default for OverrideConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            duration_minutes: 240,
+            settle_seconds: 120,
+            poll_minutes: 10,
+        }
+    }
+}
+
+#[derive(Clone, Serialize)]
+pub struct Override {
+    pub resource: String,
+    pub until: DateTime<Utc>,
+}
+
+/// Resources changed manually, which schedules should not touch for a while
+pub struct Overrides {
+    config: OverrideConfig,
+    overrides: tokio::sync::Mutex<BTreeMap<String, SystemTime>>,
+    notifier: Arc<Notifier>,
+}
+
+impl Overrides {
+    pub fn new(config: OverrideConfig, notifier: Arc<Notifier>) -> Self {
+        Self {
+            config,
+            overrides: tokio::sync::Mutex::new(BTreeMap::new()),
+            notifier,
+        }
+    }
+
+    pub async fn set(&self, rsrc: &str, until: SystemTime) {
+        log::info!(resource = rsrc; "Resource overridden until {}", timezone::from_system(until));
+        self.overrides.lock().await.insert(rsrc.to_string(), until);
+    }
+
+    /// Marks the resource changed manually for the configured duration
+    pub async fn manual(&self, rsrc: &str) {
+        self.set(rsrc, SystemTime::now() + Duration::from_secs(self.config.duration_minutes * 60)).await;
+    }
+
+    pub async fn is_overridden(&self, rsrc: &str) -> bool {
+        let mut overrides = self.overrides.lock().await;
+        match overrides.get(rsrc) {
+            Some(until) if *until > SystemTime::now() => true,
+            Some(_) => {
+                overrides.remove(rsrc);
+                drop(overrides);
+                // The manually set state is the new baseline, not a divergence from the old command
+                basic::forget_command(rsrc);
+                self.notifier.notify(Event::new(EventKind::OverrideExpired,
+                                                format!("Override of {} expired", rsrc),
+                                                format!("Schedules control {} again", rsrc))).await;
+                false
+            },
+            None => false,
+        }
+    }
+
+    pub async fn get_all(&self) -> Vec<Override> {
+        let now = SystemTime::now();
+        self.overrides.lock().await.iter()
+            .filter(|(_, until)| **until > now)
+            .map(|(rsrc, until)| Override {
+                resource: rsrc.clone(),
+                until: (*until).into(),
+            })
+            .collect()
+    }
+
+    /// Reads back the state of the resource and marks it overridden if someone changed it
+    pub async fn detect(&self, rsrc: &str) -> bool {
+        if !self.config.enabled || basic::last_command(rsrc).is_none() {
+            return false;
+        }
+
+        match basic::get_actuator(rsrc).await {
+            Ok(actual) => self.check(rsrc, &actual).await,
+            Err(_) => false,
+        }
+    }
+
+    /// Detects manual changes by reading back the state of `resources` periodically,
+    /// for actuators which are not observed
+    pub async fn poll(&self, resources: Vec<String>) {
+        if !self.config.enabled {
+            return;
+        }
+
+        loop {
+            tokio::time::sleep(Duration::from_secs(self.config.poll_minutes.max(1) * 60)).await;
+            for rsrc in &resources {
+                self.detect(rsrc).await;
+            }
+        }
+    }
+
+    /// Detects manual changes in state pushed by observed actuators
+    pub async fn process(&self, mut notifications: tokio::sync::broadcast::Receiver<Notification>) {
+        loop {
+            let notification = match notifications.recv().await {
+                Ok(notification) => notification,
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => return,
+            };
+
+            if self.config.enabled {
+                self.check(&notification.path, &notification.content).await;
+            }
+        }
+    }
+
+    async fn check(&self, rsrc: &str, actual: &[(Value, Value)]) -> bool {
+        let (commanded, time) = match basic::last_command(rsrc) {
+            Some(command) => command,
+            None => return false,
+        };
+        let settled = time.elapsed().is_ok_and(|e| e >= Duration::from_secs(self.config.settle_seconds));
+        if !settled || self.is_overridden(rsrc).await {
+            return false;
+        }
+
+        let diverged = commanded.iter()
+            .filter_map(|(key, val)| actual.iter()
+                .find(|(k, _)| k == key)
+                .map(|(_, actual_val)| (key, val, actual_val)))
+            .find(|(_, val, actual_val)| !Self::same(val, actual_val));
+
+        if let Some((key, val, actual_val)) = diverged {
+            log::info!(resource = rsrc; "Resource changed manually: {:?} is {:?} instead of {:?}", key, actual_val, val);
+            self.manual(rsrc).await;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn same(commanded: &Value, actual: &Value) -> bool {
+        if commanded == actual {
+            return true;
+        }
+
+        // The same decimal number may be encoded with different exponents
+        match (CborParser::to_decimal(commanded), CborParser::to_decimal(actual)) {
+            (Ok(commanded), Ok(actual)) => commanded == actual,
+            _ => false,
+        }
+    }
+}
@@ -0,0 +1,207 @@
+use chrono::prelude::*;
+use ciborium::value::Value;
+use serde::{Deserialize, Serialize};
+use std::process::Stdio;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+use tokio::sync::watch;
+
+use crate::actuators::Trigger;
+use crate::coap::basic;
+use crate::notify::{Event, EventKind, Notifier};
+use crate::web::Twilight;
+
+#[derive(Clone, Deserialize)]
+#[serde(default)]
+pub struct PresenceConfig {
+    /// Hosts pinged to detect people at home, e.g. addresses of phones
+    pub hosts: Vec<String>,
+    /// CoAP resources reporting presence with a "p" flag, e.g. motion sensors
+    pub resources: Vec<String>,
+    pub probe_seconds: u64,
+    /// Time without any presence signal after which home is switched to away mode
+    pub away_minutes: u64,
+    /// Times somebody usually comes home, e.g. "17:00 on workdays"
+    pub arrivals: Vec<Trigger>,
+    /// Comfort is restored this long ahead of an expected arrival while nobody is home
+    pub arrival_lead_minutes: u64,
+}
+
+impl Default for PresenceConfig {
+    fn default() -> Self {
+        Self {
+            hosts: Vec::new(),
+            resources: Vec::new(),
+            probe_seconds: 60,
+            away_minutes: 30,
+            arrivals: Vec::new(),
+            arrival_lead_minutes: 60,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PresenceState {
+    Home,
+    Away,
+}
+
+#[derive(Clone, Copy, Debug, Serialize)]
+pub struct PresenceStatus {
+    pub state: PresenceState,
+    /// None until the state changes for the first time
+    pub since: Option<DateTime<Utc>>,
+}
+
+/// Presence reported by external systems, e.g. geofencing of a phone
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Signal {
+    /// Somebody is at home
+    Seen,
+    /// Somebody is on the way home. Comfort is restored right away
+    Arriving,
+    /// Everybody left. Away mode starts right away
+    Left,
+}
+
+/// Whether anybody is at home, tracked from probes and signals
+pub struct Presence {
+    config: Option<PresenceConfig>,
+    notifier: Arc<Notifier>,
+    status: watch::Sender<PresenceStatus>,
+    last_seen: Mutex<SystemTime>,
+}
+
+impl Presence {
+    /// Without configuration home is occupied until told otherwise by a signal
+    pub fn new(config: Option<PresenceConfig>, notifier: Arc<Notifier>) -> Self {
+        Self {
+            config,
+            notifier,
+            status: watch::channel(PresenceStatus { state: PresenceState::Home, since: None }).0,
+            last_seen: Mutex::new(SystemTime::now()),
+        }
+    }
+
+    pub fn status(&self) -> PresenceStatus {
+        *self.status.borrow()
+    }
+
+    pub fn is_away(&self) -> bool {
+        self.status().state == PresenceState::Away
+    }
+
+    /// Receiver notified when home switches between home and away
+    pub fn subscribe(&self) -> watch::Receiver<PresenceStatus> {
+        self.status.subscribe()
+    }
+
+    pub async fn signal(&self, signal: Signal, source: &str) {
+        log::debug!(source = source; "Presence signal {:?}", signal);
+        match signal {
+            Signal::Seen | Signal::Arriving => {
+                *self.last_seen.lock().unwrap() = SystemTime::now();
+                self.set_state(PresenceState::Home, source).await;
+            },
+            Signal::Left => self.set_state(PresenceState::Away, source).await,
+        }
+    }
+
+    async fn set_state(&self, state: PresenceState, source: &str) {
+        let changed = self.status.send_if_modified(|status| {
+            if status.state == state {
+                return false;
+            }
+            *status = PresenceStatus { state, since: Some(Utc::now()) };
+            true
+        });
+        if !changed {
+            return;
+        }
+
+        log::info!(source = source; "Presence changed to {:?}", state);
+        let event = match state {
+            PresenceState::Home => Event::new(EventKind::Arrival, "Somebody is home".to_string(),
+                                              format!("Comfort schedules restored after a signal from {}", source)),
+            PresenceState::Away => Event::new(EventKind::Departure, "Nobody is home".to_string(),
+                                              format!("Away mode started after a signal from {}", source)),
+        };
+        self.notifier.notify(event).await;
+    }
+
+    async fn ping(host: &str) -> bool {
+        let status = tokio::process::Command::new("ping")
+            .args(["-c", "1", "-W", "2", host])
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status().await;
+        status.is_ok_and(|s| s.success())
+    }
+
+    async fn read_resource(rsrc: &str) -> Result<bool, String> {
+        let map = basic::get_actuator(rsrc).await?;
+        let value = map.iter()
+            .find(|e| e.0.as_text().is_some_and(|t| t == "p"))
+            .map(|e| &e.1)
+            .ok_or(format!("No presence reported by {}", rsrc))?;
+
+        match value {
+            Value::Bool(present) => Ok(*present),
+            Value::Integer(present) => Ok(i128::from(*present) != 0),
+            _ => Err(format!("Unexpected presence value returned by {}", rsrc)),
+        }
+    }
+
+    /// First of the expected arrivals after `after`
+    async fn next_arrival(arrivals: &[Trigger], after: SystemTime, twilight: &Twilight) -> Option<SystemTime> {
+        let mut next = None;
+        for arrival in arrivals {
+            match arrival.next_after(after, twilight).await {
+                Ok(time) => next = Some(next.map_or(time, |next: SystemTime| next.min(time))),
+                Err(e) => log::warn!("Skipping expected arrival at {}: {}", arrival, e),
+            }
+        }
+        next
+    }
+
+    /// Probes the configured hosts and resources and switches to away mode when nobody is seen.
+    /// Ahead of expected arrivals home is switched back, staying so until the arrival is overdue
+    pub async fn process(&self, twilight: Arc<Twilight>) {
+        let Some(config) = &self.config else { return };
+        let lead = Duration::from_secs(config.arrival_lead_minutes * 60);
+        let mut arrival = Self::next_arrival(&config.arrivals, SystemTime::now() + lead, &twilight).await;
+
+        loop {
+            if let Some(time) = arrival.filter(|t| SystemTime::now() + lead >= *t) {
+                if self.is_away() {
+                    self.signal(Signal::Arriving, "expected arrival").await;
+                    *self.last_seen.lock().unwrap() = time;
+                }
+                arrival = Self::next_arrival(&config.arrivals, time, &twilight).await;
+            }
+
+            for host in &config.hosts {
+                if Self::ping(host).await {
+                    self.signal(Signal::Seen, host).await;
+                }
+            }
+            for rsrc in &config.resources {
+                match Self::read_resource(rsrc).await {
+                    Ok(true) => self.signal(Signal::Seen, rsrc).await,
+                    Ok(false) => (),
+                    Err(e) => log::warn!(resource = rsrc.as_str(); "Error reading presence: {}", e),
+                }
+            }
+
+            let last_seen = *self.last_seen.lock().unwrap();
+            let unseen = SystemTime::now().duration_since(last_seen).unwrap_or_default();
+            if unseen >= Duration::from_secs(config.away_minutes * 60) {
+                self.set_state(PresenceState::Away, "timeout").await;
+            }
+
+            tokio::time::sleep(Duration::from_secs(config.probe_seconds)).await;
+        }
+    }
+}
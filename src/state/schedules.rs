@@ -0,0 +1,88 @@
+use chrono::prelude::*;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use tokio::sync::{broadcast, watch};
+
+use crate::actuators::cron_processor::Planned;
+
+/// Outcome of the last attempt to apply a scheduled action to a resource
+#[derive(Clone, Debug, Serialize)]
+pub struct ActionResult {
+    pub resource: String,
+    pub time: DateTime<Utc>,
+    pub error: Option<String>,
+}
+
+/// Upcoming actions of all running schedules, their results, and requests to run them early
+/// or to build them again
+pub struct Schedules {
+    upcoming: watch::Sender<BTreeMap<String, Planned>>,
+    results: broadcast::Sender<ActionResult>,
+    triggers: broadcast::Sender<String>,
+    refreshes: broadcast::Sender<()>,
+}
+
+impl Schedules {
+    pub fn new() -> Self {
+        Self {
+            upcoming: watch::channel(BTreeMap::new()).0,
+            results: broadcast::channel(16).0,
+            triggers: broadcast::channel(4).0,
+            refreshes: broadcast::channel(1).0,
+        }
+    }
+
+    pub fn set_next(&self, schedule: &str, planned: Planned) {
+        self.upcoming.send_modify(|upcoming| {
+            upcoming.insert(schedule.to_string(), planned);
+        });
+    }
+
+    /// The schedule has nothing planned
+    pub fn clear_next(&self, schedule: &str) {
+        self.upcoming.send_if_modified(|upcoming| upcoming.remove(schedule).is_some());
+    }
+
+    pub fn upcoming(&self) -> BTreeMap<String, Planned> {
+        self.upcoming.borrow().clone()
+    }
+
+    /// Receiver notified each time any schedule picks its next action
+    pub fn subscribe_upcoming(&self) -> watch::Receiver<BTreeMap<String, Planned>> {
+        self.upcoming.subscribe()
+    }
+
+    pub fn report(&self, resource: &str, result: &Result<(), String>) {
+        let _ = self.results.send(ActionResult {
+            resource: resource.to_string(),
+            time: Utc::now(),
+            error: result.as_ref().err().cloned(),
+        });
+    }
+
+    pub fn subscribe_results(&self) -> broadcast::Receiver<ActionResult> {
+        self.results.subscribe()
+    }
+
+    /// Runs the pending action of the schedule now instead of at its time
+    pub fn trigger(&self, schedule: &str) -> Result<(), String> {
+        if !self.upcoming.borrow().contains_key(schedule) {
+            return Err(format!("Unknown schedule {}", schedule));
+        }
+        self.triggers.send(schedule.to_string()).map_err(|e| e.to_string())?;
+        Ok(())
+    }
+
+    pub fn subscribe_triggers(&self) -> broadcast::Receiver<String> {
+        self.triggers.subscribe()
+    }
+
+    /// Makes all schedules build their actions again, e.g. after something they depend on changed
+    pub fn refresh(&self) {
+        let _ = self.refreshes.send(());
+    }
+
+    pub fn subscribe_refreshes(&self) -> broadcast::Receiver<()> {
+        self.refreshes.subscribe()
+    }
+}
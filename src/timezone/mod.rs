@@ -0,0 +1,156 @@
+//! Time zone of schedules and local times skipped or repeated by daylight saving time changes
+
+use chrono::prelude::*;
+use chrono::LocalResult;
+use chrono_tz::Tz;
+use std::str::FromStr;
+use std::sync::OnceLock;
+use std::time::SystemTime;
+
+static ZONE: OnceLock<Option<Tz>> = OnceLock::new();
+
+/// Sets the time zone schedules are resolved in. The host time zone is used if not set
+pub fn init(tz: Option<Tz>) {
+    if ZONE.set(tz).is_err() {
+        log::warn!("Time zone already set");
+    }
+}
+
+fn zone() -> Option<Tz> {
+    ZONE.get().copied().flatten()
+}
+
+/// `time` in the configured time zone
+pub fn from_system(time: SystemTime) -> DateTime<FixedOffset> {
+    let time = DateTime::<Utc>::from(time);
+    match zone() {
+        Some(tz) => time.with_timezone(&tz).fixed_offset(),
+        None => time.with_timezone(&Local).fixed_offset(),
+    }
+}
+
+fn from_local(time: NaiveDateTime) -> LocalResult<DateTime<FixedOffset>> {
+    match zone() {
+        Some(tz) => tz.from_local_datetime(&time).map(|t| t.fixed_offset()),
+        None => Local.from_local_datetime(&time).map(|t| t.fixed_offset()),
+    }
+}
+
+/// What happens to a local time skipped when clocks move forward
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum GapPolicy {
+    /// No occurrence on that day
+    Skip,
+    /// Moved by the length of the gap, e.g. 02:30 becomes 03:30
+    #[default]
+    ShiftForward,
+}
+
+/// What happens to a local time repeated when clocks move back
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum FoldPolicy {
+    /// Only the first occurrence
+    #[default]
+    Once,
+    /// Both before and after the change
+    Both,
+}
+
+/// Handling of both kinds of daylight saving time changes, e.g. "skip,both"
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct DstPolicy {
+    pub gap: GapPolicy,
+    pub fold: FoldPolicy,
+}
+
+impl FromStr for DstPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut policy = Self::default();
+        for part in s.split(',') {
+            match part {
+                "skip" => policy.gap = GapPolicy::Skip,
+                "shift_forward" => policy.gap = GapPolicy::ShiftForward,
+                "once" => policy.fold = FoldPolicy::Once,
+                "both" => policy.fold = FoldPolicy::Both,
+                _ => return Err(format!("Unknown DST policy {}", part)),
+            }
+        }
+        Ok(policy)
+    }
+}
+
+/// Instants at which the local `time` occurs, in order. None, one or two depending on the policy
+pub fn resolve(time: NaiveDateTime, policy: DstPolicy) -> Vec<SystemTime> {
+    match from_local(time) {
+        LocalResult::Single(t) => vec![t.into()],
+        LocalResult::Ambiguous(first, second) => match policy.fold {
+            FoldPolicy::Once => vec![first.into()],
+            FoldPolicy::Both => vec![first.into(), second.into()],
+        },
+        LocalResult::None => match policy.gap {
+            GapPolicy::Skip => Vec::new(),
+            GapPolicy::ShiftForward => {
+                // Applying the offset from before the gap moves the time past it by the gap length
+                let before = (1..=48)
+                    .find_map(|i| from_local(time - chrono::Duration::minutes(30 * i)).earliest());
+                before.map(|before| {
+                    let offset = chrono::Duration::seconds(i64::from(before.offset().local_minus_utc()));
+                    SystemTime::from((time - offset).and_utc())
+                }).into_iter().collect()
+            },
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn warsaw() {
+        init(Some(chrono_tz::Europe::Warsaw));
+    }
+
+    fn local(time: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(time, "%Y-%m-%d %H:%M").unwrap()
+    }
+
+    fn utc(time: &str) -> SystemTime {
+        DateTime::parse_from_rfc3339(time).unwrap().into()
+    }
+
+    #[test]
+    fn policy() {
+        assert_eq!("skip,both".parse(), Ok(DstPolicy { gap: GapPolicy::Skip, fold: FoldPolicy::Both }));
+        assert_eq!("once".parse(), Ok(DstPolicy { gap: GapPolicy::ShiftForward, fold: FoldPolicy::Once }));
+        assert!("twice".parse::<DstPolicy>().is_err());
+    }
+
+    #[test]
+    fn gap() {
+        warsaw();
+        let time = local("2026-03-29 02:30");
+        assert_eq!(resolve(time, "skip".parse().unwrap()), vec![]);
+        // 03:30 CEST
+        assert_eq!(resolve(time, "shift_forward".parse().unwrap()), vec![utc("2026-03-29T01:30:00Z")]);
+    }
+
+    #[test]
+    fn fold() {
+        warsaw();
+        let time = local("2026-10-25 02:30");
+        // 02:30 CEST and 02:30 CET
+        assert_eq!(resolve(time, "once".parse().unwrap()), vec![utc("2026-10-25T00:30:00Z")]);
+        assert_eq!(resolve(time, "both".parse().unwrap()), vec![utc("2026-10-25T00:30:00Z"), utc("2026-10-25T01:30:00Z")]);
+    }
+
+    #[test]
+    fn unambiguous() {
+        warsaw();
+        let time = local("2026-07-01 12:00");
+        for policy in ["skip,once", "shift_forward,both"] {
+            assert_eq!(resolve(time, policy.parse().unwrap()), vec![utc("2026-07-01T10:00:00Z")]);
+        }
+    }
+}
@@ -1,19 +1,35 @@
 use chrono::prelude::*;
 use rust_decimal::prelude::*;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use crate::notify::Notifier;
+
+/// Location of the home, the same as used for sun data
+const LATITUDE: f64 = 50.061389;
+const LONGITUDE: f64 = 19.938333;
 
 pub struct Moon
 {
     qweather_key: String,
+    notifier: Arc<Notifier>,
 }
 
 impl Moon {
-    pub fn new(qweather_key: &str) -> Self {
+    pub fn new(qweather_key: &str, notifier: Arc<Notifier>) -> Self {
         Self {
             qweather_key: qweather_key.to_string(),
+            notifier,
         }
     }
 
     pub async fn get_phase(&self) -> Result<Decimal, String> {
+        let result = self.fetch_phase().await;
+        self.notifier.provider_result("QWeather", &result).await;
+        result
+    }
+
+    async fn fetch_phase(&self) -> Result<Decimal, String> {
         let tomorrow = Utc::now().date_naive().succ_opt().unwrap();
         let url = format!("https://devapi.qweather.com/v7/astronomy/moon?location=27523&date={}&key={}&lang=en",
                           tomorrow.format("%Y%m%d"),
@@ -32,4 +48,47 @@ impl Moon {
             Err("Unexpected type of \"moonPhase\"'s \"value\"".to_string())
         }
     }
+
+    /// Illuminated fraction of the disc, from 0 at new moon to 1 at full moon
+    pub fn illumination(phase: Decimal) -> f64 {
+        let phase = f64::try_from(phase).unwrap_or_default();
+        (1.0 - (2.0 * std::f64::consts::PI * phase).cos()) / 2.0
+    }
+
+    /// Altitude of the moon above the horizon in degrees, from low precision formulae of
+    /// the Astronomical Almanac. Accurate to about half a degree
+    pub fn altitude(time: SystemTime) -> f64 {
+        let sin = |deg: f64| deg.to_radians().sin();
+
+        let time = DateTime::<Utc>::from(time);
+        // Days since J2000.0
+        let d = (time.timestamp() as f64 - 946_728_000.0) / 86_400.0;
+        let t = d / 36_525.0;
+
+        let lambda = 218.32 + 481_267.881 * t
+            + 6.29 * sin(135.0 + 477_198.87 * t) - 1.27 * sin(259.3 - 413_335.36 * t)
+            + 0.66 * sin(235.7 + 890_534.22 * t) + 0.21 * sin(269.9 + 954_397.74 * t)
+            - 0.19 * sin(357.5 + 35_999.05 * t) - 0.11 * sin(186.5 + 966_404.03 * t);
+        let beta = 5.13 * sin(93.3 + 483_202.02 * t) + 0.28 * sin(228.2 + 960_400.89 * t)
+            - 0.28 * sin(318.3 + 6_003.15 * t) - 0.17 * sin(217.6 - 407_332.21 * t);
+        let parallax = 0.9508 + 0.0518 * (134.9 + 477_198.85 * t).to_radians().cos();
+        let epsilon = (23.439 - 0.000_000_4 * d).to_radians();
+        let (lambda, beta) = (lambda.to_radians(), beta.to_radians());
+
+        // Ecliptic to equatorial coordinates
+        let x = beta.cos() * lambda.cos();
+        let y = epsilon.cos() * beta.cos() * lambda.sin() - epsilon.sin() * beta.sin();
+        let z = epsilon.sin() * beta.cos() * lambda.sin() + epsilon.cos() * beta.sin();
+        let right_ascension = y.atan2(x);
+        let declination = z.asin();
+
+        let sidereal = (280.460_618_37 + 360.985_647_366_29 * d + LONGITUDE).to_radians();
+        let hour_angle = sidereal - right_ascension;
+        let latitude = LATITUDE.to_radians();
+        let altitude = (latitude.sin() * declination.sin()
+                        + latitude.cos() * declination.cos() * hour_angle.cos()).asin();
+
+        // The moon is close enough for the observer's position on Earth to matter
+        altitude.to_degrees() - parallax * altitude.cos()
+    }
 }
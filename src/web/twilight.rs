@@ -1,70 +1,101 @@
 use chrono::prelude::*;
 use serde::Deserialize;
 use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
 use std::time::SystemTime;
 
+use crate::actuators::SolarEvent;
+use crate::notify::Notifier;
+use crate::timezone;
 
-// TODO: Some kind of cache?
-pub struct Twilight();
+/// Days of solar events kept in the cache
+const CACHED_DAYS: usize = 16;
+
+pub struct Twilight {
+    notifier: Arc<Notifier>,
+    events: Mutex<BTreeMap<NaiveDate, BTreeMap<String, SystemTime>>>,
+}
 
 impl Twilight {
-    pub fn new() -> Self {
-        Twilight()
+    pub fn new(notifier: Arc<Notifier>) -> Self {
+        Twilight {
+            notifier,
+            events: Mutex::new(BTreeMap::new()),
+        }
     }
 
-    pub async fn get_pair(&self) -> Result<[SystemTime; 2], String> {
-        #[derive(Deserialize)]
-        struct SunData {
-            results: BTreeMap<String, String>,
-            status: String,
-        }
+    /// Time of the solar event on `day`. Days already fetched are served from the cache
+    pub async fn get_event(&self, day: NaiveDate, event: SolarEvent) -> Result<SystemTime, String> {
+        let cached = self.events.lock().unwrap().get(&day).cloned();
+        let events = match cached {
+            Some(events) => events,
+            None => {
+                let result = Self::fetch_events(day).await;
+                self.notifier.provider_result("sunrise-sunset.org", &result).await;
+                let events = result?;
 
-        async fn sun_time_get(day: NaiveDate) -> Result<SunData, reqwest::Error> 
-        {
-            let result = reqwest::get(format!("https://api.sunrise-sunset.org/json?lat=50.061389&lng=19.938333&date={}",
-                                              &day.format("%Y-%m-%d").to_string()
-                                             )).await?
-                         .json::<SunData>().await?;
-            Ok(result)
-        }
+                let mut cache = self.events.lock().unwrap();
+                cache.insert(day, events.clone());
+                while cache.len() > CACHED_DAYS {
+                    cache.pop_first();
+                }
+                events
+            },
+        };
 
-        let today = Utc::now().date_naive();
-        let tomorrow = today.succ_opt().unwrap();
+        events.get(event.api_name()).copied()
+            .ok_or(format!("Missing {} in retrieved sun data", event.api_name()))
+    }
 
-        let sun_data_today = sun_time_get(today).await.map_err(|e| e.to_string())?;
-        let sun_data_tomorrow = sun_time_get(tomorrow).await.map_err(|e| e.to_string())?;
+    /// Fills the cache with events of `day`, so they are not fetched
+    #[cfg(test)]
+    pub fn set_events(&self, day: NaiveDate, events: &[(SolarEvent, SystemTime)]) {
+        let events = events.iter().map(|(event, time)| (event.api_name().to_string(), *time)).collect();
+        self.events.lock().unwrap().insert(day, events);
+    }
 
-        if sun_data_today.status != "OK".to_string() {
-            return Err("Status of retrieved today sun data is not OK".to_string());
+    async fn fetch_events(day: NaiveDate) -> Result<BTreeMap<String, SystemTime>, String> {
+        #[derive(Deserialize)]
+        struct SunData {
+            results: BTreeMap<String, serde_json::Value>,
+            status: String,
         }
-        if sun_data_tomorrow.status != "OK".to_string() {
-            return Err("Status of retrieved tomorrow sun data is not OK".to_string());
+
+        let sun_data = reqwest::get(format!("https://api.sunrise-sunset.org/json?lat=50.061389&lng=19.938333&date={}&formatted=0",
+                                            &day.format("%Y-%m-%d").to_string()
+                                           )).await.map_err(|e| e.to_string())?
+                       .json::<SunData>().await.map_err(|e| e.to_string())?;
+        if sun_data.status != "OK" {
+            return Err(format!("Status of retrieved sun data for {} is not OK", day));
         }
 
-        if let (Some(twilight_begin_today_str), Some(twilight_end_today_str), 
-                Some(twilight_begin_tomorrow_str), Some(twilight_end_tomorrow_str)) = 
-                (sun_data_today.results.get(&"civil_twilight_begin".to_string()),
-                 sun_data_today.results.get(&"civil_twilight_end".to_string()),
-                 sun_data_tomorrow.results.get(&"civil_twilight_begin".to_string()),
-                 sun_data_tomorrow.results.get(&"civil_twilight_end".to_string())) {
-            let twilight_beg_today_time = NaiveTime::parse_from_str(twilight_begin_today_str, "%r").map_err(|e| e.to_string())?;
-            let twilight_end_today_time = NaiveTime::parse_from_str(twilight_end_today_str, "%r").map_err(|e| e.to_string())?;
-            let twilight_beg_tomorrow_time = NaiveTime::parse_from_str(twilight_begin_tomorrow_str, "%r").map_err(|e| e.to_string())?;
-            let twilight_end_tomorrow_time = NaiveTime::parse_from_str(twilight_end_tomorrow_str, "%r").map_err(|e| e.to_string())?;
+        // Only timestamps are kept, day_length is a number of seconds
+        Ok(sun_data.results.into_iter()
+            .filter_map(|(name, value)| {
+                let time = DateTime::parse_from_rfc3339(value.as_str()?).ok()?;
+                Some((name, SystemTime::from(time)))
+            })
+            .collect())
+    }
 
-            let now = Utc::now().naive_utc();
-            let twilight_begin_today = today.and_time(twilight_beg_today_time);
-            let twilight_end_today = today.and_time(twilight_end_today_time);
-            let twilight_begin_tomorrow = tomorrow.and_time(twilight_beg_tomorrow_time);
-            let twilight_end_tomorrow = tomorrow.and_time(twilight_end_tomorrow_time);
+    pub async fn get_pair(&self) -> Result<[SystemTime; 2], String> {
+        self.get_pair_after(SystemTime::now()).await
+    }
 
-            let twilight_begin = if now > twilight_begin_today { twilight_begin_tomorrow } else { twilight_begin_today };
-            let twilight_end = if now > twilight_end_today { twilight_end_tomorrow } else {twilight_end_today };
+    /// Next twilight begin and end following `after`
+    pub async fn get_pair_after(&self, after: SystemTime) -> Result<[SystemTime; 2], String> {
+        Ok([self.next_event(SolarEvent::CivilDawn, after).await?,
+            self.next_event(SolarEvent::CivilDusk, after).await?])
+    }
 
-            Ok([twilight_begin.and_utc().try_into().unwrap(),
-                twilight_end.and_utc().try_into().unwrap()])
-        } else {
-            Err("Missing twilight time in retrieved sun data".to_string())
+    async fn next_event(&self, event: SolarEvent, after: SystemTime) -> Result<SystemTime, String> {
+        let today = timezone::from_system(after).date_naive();
+        for day in today.iter_days().take(2) {
+            let time = self.get_event(day, event).await?;
+            if time > after {
+                return Ok(time);
+            }
         }
+        Err(format!("No {} in retrieved sun data", event.api_name()))
     }
 }
@@ -1,12 +1,16 @@
 use chrono::prelude::*;
 use chrono::DateTime;
 use rust_decimal::prelude::*;
+use std::sync::Arc;
 use std::time::Duration;
 
+use crate::notify::Notifier;
+
 #[derive(Debug)]
 pub struct Forecast
 {
     temperature: Decimal,
+    temperatures: Vec<Decimal>,
     cloudiness: u32,
 }
 
@@ -15,6 +19,10 @@ impl Forecast {
         self.temperature
     }
 
+    pub fn get_temperatures(&self) -> &[Decimal] {
+        &self.temperatures
+    }
+
     pub fn get_cloudiness(&self) -> u32 {
         self.cloudiness
     }
@@ -24,13 +32,15 @@ pub struct Weather
 {
     openweather_key: Option<String>,
     visualcrossing_key: Option<String>,
+    notifier: Arc<Notifier>,
 }
 
 impl Weather {
-    pub fn new(openweather_key: Option<String>, visualcrossing_key: Option<String>) -> Self {
+    pub fn new(openweather_key: Option<String>, visualcrossing_key: Option<String>, notifier: Arc<Notifier>) -> Self {
         Weather {
             openweather_key,
             visualcrossing_key,
+            notifier,
         }
     }
 
@@ -38,6 +48,22 @@ impl Weather {
     where
     Tz: TimeZone,
     Tz::Offset: std::fmt::Display,
+    {
+        let result = self.fetch_temperature_history(start_time, end_time).await;
+        self.notifier.provider_result("Visual Crossing", &result).await;
+        result
+    }
+
+    pub async fn get_forecast(&self, dur: &Duration) -> Result<Forecast, String> {
+        let result = self.fetch_forecast(dur).await;
+        self.notifier.provider_result("OpenWeatherMap", &result).await;
+        result
+    }
+
+    async fn fetch_temperature_history<Tz>(&self, start_time: DateTime<Tz>, end_time: DateTime<Tz>) -> Result<Vec<Decimal>, String> 
+    where
+    Tz: TimeZone,
+    Tz::Offset: std::fmt::Display,
     {
         let url = format!("https://weather.visualcrossing.com/VisualCrossingWebServices/rest/services/timeline/Krakow,PL/{}/{}?include=hours&elements=datetimeEpoch,temp&unitGroup=metric&key={}",
                           start_time.format("%Y-%m-%d"),
@@ -79,7 +105,7 @@ impl Weather {
             .collect::<Result<Vec<Decimal>, String>>()
     }
 
-    pub async fn get_forecast(&self, dur: &Duration) -> Result<Forecast, String> {
+    async fn fetch_forecast(&self, dur: &Duration) -> Result<Forecast, String> {
         let secs_in_3_hours = 3600u64 * 3u64;
         let cnt = (dur.as_secs() + secs_in_3_hours - 1) / secs_in_3_hours;
         let url = format!("https://api.openweathermap.org/data/2.5/forecast?lat=50.061389&lon=19.938333&appid={}&units=metric&cnt={}",
@@ -97,12 +123,15 @@ impl Weather {
         let mut forecast = Forecast {
             cloudiness: 0,
             temperature: Decimal::new(0, 0),
+            temperatures: Vec::with_capacity(list.len()),
         };
         for item in list {
             if let serde_json::value::Value::Number(temperature) = item
                     .get("main").ok_or("Missing \"main\" entry in one element in the list")?
                     .get("temp").ok_or("Missing \"temp\" for \"main\"")? {
-                temp += temperature.as_f64().ok_or("Temperature cannot be converted to f64")?;
+                let temperature = temperature.as_f64().ok_or("Temperature cannot be converted to f64")?;
+                temp += temperature;
+                forecast.temperatures.push(Decimal::from_f64(temperature).ok_or(format!("Cannot convert {} to Decimal", temperature))?);
             } else {
                 return Err("Unexpected type of \"temp\" for \"main\"".to_string());
             }